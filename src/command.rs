@@ -1,143 +1,623 @@
 use std::{
-    fs::{self, create_dir_all, write, File},
-    io,
-    path::PathBuf,
+    env, fs,
+    io::{self, BufRead, BufWriter, Read, Write},
+    path::{Path, PathBuf},
 };
 
+use regex::Regex;
+
 use crate::{
-    index::{Index, IndexEntry},
-    object::{Blob, Commit, Oid, Tree, TreeEntry},
+    config::{
+        canonical_key, parse_bool, parse_int, verify_key, Config, ConfigEntry, ConfigFile,
+        ConfigScope, ValuePattern,
+    },
+    fsck::FsckIssue,
+    index::{
+        parse_cacheinfo, parse_index_info, verify_path, EntryStatus, Index, IndexEntry,
+        RefreshIssue, RefreshOptions,
+    },
+    object::{normalize_mode, parse_expiry_date, ObjectKind, Oid, RawObject, Tag, Tree},
+    pack::{write_pack, DeltaOptions},
     repository::{
-        blob, create_object_path, get_git_dir, get_object_path, get_reference_path,
-        get_reference_relative_path, GitResult, DEFAULT_BRANCH, DEFAULT_CONTENT,
+        get_init_git_dir, is_valid_reference_name, write_gitfile, GitError, GitResult, InitOptions,
+        RepackOptions, Repository, RepositoryLocation, GC_AUTO_THRESHOLD, GIT_DIR,
     },
+    utils::wildmatch,
 };
 
-pub fn init() -> GitResult<()> {
-    let git_dir = get_git_dir();
-
-    create_dir_all(&git_dir)?;
-
-    let head = git_dir.join("HEAD");
-    if !head.exists() {
-        write(
-            head,
-            format!(
-                "ref: {}",
-                get_reference_relative_path(DEFAULT_BRANCH)
-                    .into_os_string()
-                    .into_string()
-                    .expect("references are always utf8")
-            )
-            .as_bytes(),
-        )?;
-    }
+/// unreachable objects younger than this are kept by `gc`, as they may be
+/// about to be referenced by a concurrent command
+const GC_PRUNE_EXPIRE: &str = "2.weeks.ago";
+const REFLOG_EXPIRE: &str = "90.days.ago";
 
-    let config = git_dir.join("config");
-    if !config.exists() {
-        write(config, DEFAULT_CONTENT.as_bytes())?;
-    }
-
-    let branches = git_dir.join("branches");
-    let hooks = git_dir.join("hooks");
-    let info = git_dir.join("info");
-    create_dir_all(branches)?;
-    create_dir_all(hooks)?;
-    create_dir_all(info)?;
+/// Creates a repository in the directory, or the current one, which is
+/// created if needed. With a separate git dir, the working tree only gets a
+/// `.grit` file pointing to it
+pub fn init(
+    directory: Option<PathBuf>,
+    bare: bool,
+    separate_git_dir: Option<PathBuf>,
+    options: InitOptions,
+) -> GitResult<()> {
+    let directory = match directory {
+        Some(directory) => {
+            fs::create_dir_all(&directory)?;
+            directory.canonicalize()?
+        }
+        None => env::current_dir()?,
+    };
 
-    let objects = git_dir.join("objects");
-    let objects_info = objects.join("objects_info");
-    let objects_pack = objects.join("objects_pack");
-    create_dir_all(objects_info)?;
-    create_dir_all(objects_pack)?;
+    let work_tree = (!bare).then(|| directory.clone());
+    let git_dir = match &separate_git_dir {
+        Some(git_dir) => env::current_dir()?.join(git_dir),
+        None => get_init_git_dir(&directory, bare),
+    };
 
-    let refs = git_dir.join("refs");
-    let refs_heads = refs.join("heads");
-    let refs_tags = refs.join("tags");
-    create_dir_all(refs_heads)?;
-    create_dir_all(refs_tags)?;
+    Repository::init(git_dir.clone(), work_tree, &options)?;
+    if separate_git_dir.is_some() {
+        write_gitfile(&directory, &git_dir)?;
+    }
 
     Ok(())
 }
 
 pub fn hash_object(file: PathBuf) -> GitResult<()> {
-    let blob_id = blob(&file)?;
+    let repository = Repository::discover()?;
+    let blob_id = repository.write_blob(fs::read(file)?)?;
 
     println!("{blob_id}");
 
     Ok(())
 }
 
-pub fn cat_file(id: String) -> GitResult<()> {
+pub enum CatFileMode {
+    /// Print the raw content, checking that the object has the given type
+    Kind(ObjectKind),
+    Type,
+    Size,
+    Pretty,
+}
+
+pub fn cat_file(revision: String, mode: CatFileMode) -> GitResult<()> {
+    let repository = Repository::discover()?;
+    let oid = repository.resolve_revision(&revision)?;
+    let mut stdout = io::stdout().lock();
+
+    match mode {
+        CatFileMode::Kind(kind) => {
+            let object = repository.read_raw_object(&oid)?;
+            if object.kind() != kind {
+                return Err(GitError::UnexpectedObjectKind {
+                    expected: kind,
+                    found: object.kind(),
+                });
+            }
+            stdout.write_all(object.content())?;
+        }
+        CatFileMode::Type => {
+            let (kind, _) = repository.read_object_header(&oid)?;
+            writeln!(stdout, "{kind}")?;
+        }
+        CatFileMode::Size => {
+            let (_, size) = repository.read_object_header(&oid)?;
+            writeln!(stdout, "{size}")?;
+        }
+        CatFileMode::Pretty => {
+            let object = repository.read_raw_object(&oid)?;
+            match object.kind() {
+                ObjectKind::Tree => {
+                    let tree = Tree::deserialize_content(object.content())?;
+                    for entry in tree.entries() {
+                        writeln!(
+                            stdout,
+                            "{:06o} {} {}\t{}",
+                            entry.mode(),
+                            entry.kind(),
+                            entry.oid(),
+                            entry.name()
+                        )?;
+                    }
+                }
+                _ => stdout.write_all(object.content())?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether the object exists, failing only if the id is malformed
+pub fn cat_file_exists(id: String) -> GitResult<bool> {
     let oid = Oid::new(id)?;
 
-    let git_dir = get_git_dir();
-    let blob_path = get_object_path(&git_dir, &oid);
-    let blob_file = File::open(blob_path)?;
+    Repository::discover()?.object_exists(&oid)
+}
+
+/// Reads object ids from stdin, one per line, and prints their type and size
+/// followed by their content if `contents` is set
+pub fn cat_file_batch(contents: bool) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    let stdin = io::stdin().lock();
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    for line in stdin.lines() {
+        let line = line?;
+        let id = line.trim();
+
+        let object = match Oid::new(id) {
+            Ok(oid) if contents => repository.read_raw_object(&oid).map(|object| {
+                let header = (object.kind(), object.size());
+                (oid, header, Some(object))
+            }),
+            Ok(oid) => repository
+                .read_object_header(&oid)
+                .map(|header| (oid, header, None)),
+            Err(error) => Err(error),
+        };
+
+        match object {
+            Ok((oid, (kind, size), object)) => {
+                writeln!(stdout, "{oid} {kind} {size}")?;
+                if let Some(object) = object {
+                    stdout.write_all(object.content())?;
+                    writeln!(stdout)?;
+                }
+            }
+            Err(GitError::ObjectNotFound(_) | GitError::InvalidOid) => {
+                writeln!(stdout, "{id} missing")?;
+            }
+            Err(error) => return Err(error),
+        }
+    }
 
-    Blob::deserialize(blob_file, io::stdout())?;
+    stdout.flush()?;
 
     Ok(())
 }
 
-pub fn update_index(file: PathBuf) -> GitResult<()> {
-    let git_dir = get_git_dir();
+pub struct UpdateIndexOptions {
+    /// Add files which are not in the index yet
+    pub add: bool,
+    /// Remove entries whose file is missing
+    pub remove: bool,
+    /// Remove entries even if their file still exists
+    pub force_remove: bool,
+    /// Entries to add without reading a file, as `<mode>,<oid>,<path>`
+    pub cacheinfo: Vec<String>,
+    /// Set or clear the executable bit of the given paths
+    pub chmod: Option<bool>,
+    /// Set or clear the assume unchanged bit, instead of updating the paths
+    pub assume_unchanged: Option<bool>,
+    /// Set or clear the skip worktree bit, instead of updating the paths
+    pub skip_worktree: Option<bool>,
+    /// Read entries from stdin, as printed by `ls-files --stage` or `ls-tree`
+    pub index_info: bool,
+    /// Restore the conflicting stages of the paths, instead of updating them
+    pub unresolve: bool,
+    pub index_version: Option<u32>,
+    /// Lines read from stdin are terminated by a null byte
+    pub zero_terminated: bool,
+    /// Cache the stat data of unchanged entries, reporting the others
+    pub refresh: bool,
+    /// Refresh entries marked as assume unchanged too
+    pub really_refresh: bool,
+    /// Do not report entries needing an update, nor fail because of them
+    pub quiet: bool,
+    /// Do not report entries whose file is missing when refreshing
+    pub ignore_missing: bool,
+}
+
+/// Updates the index entries of files, or of entries given directly,
+/// returning whether every entry was up to date when refreshing
+pub fn update_index(paths: Vec<PathBuf>, options: UpdateIndexOptions) -> GitResult<bool> {
+    let repository = Repository::discover()?;
+
+    let mut index = repository.index()?;
+    let mut up_to_date = true;
+
+    if let Some(version) = options.index_version {
+        index.set_version(version)?;
+    }
+
+    if options.refresh || options.really_refresh {
+        let refresh_options = RefreshOptions {
+            ignore_missing: options.ignore_missing,
+            really: options.really_refresh,
+        };
 
-    let index_path = git_dir.join("index");
-    let mut index = Index::deserialize_from_path(&index_path).unwrap_or_default();
+        for (name, issue) in index.refresh(repository.require_work_tree()?, refresh_options)? {
+            if options.quiet {
+                continue;
+            }
+            match issue {
+                RefreshIssue::NeedsUpdate => println!("{name}: needs update"),
+                RefreshIssue::NeedsMerge => println!("{name}: needs merge"),
+            }
+            up_to_date = false;
+        }
+    }
 
-    let blob_id = blob(&file)?;
+    for cacheinfo in &options.cacheinfo {
+        let (mode, oid, name) = parse_cacheinfo(cacheinfo)?;
+        verify_path(&name)?;
 
-    let entry = {
-        let metadata = fs::metadata(&file)?;
-        let name = file
-            .into_os_string()
-            .into_string()
-            .expect("filename is not utf8");
-        IndexEntry::new(metadata, blob_id, false, 0, name)?
+        if !options.add && !index.contains(&name) {
+            return Err(GitError::MissingAddOption(name));
+        }
+        index.push(IndexEntry::without_stat(
+            normalize_mode(mode)?,
+            oid,
+            0,
+            name,
+        ));
+    }
+
+    for path in paths {
+        let name = repository.normalize_path(&path)?;
+
+        if options.assume_unchanged.is_some() || options.skip_worktree.is_some() {
+            let mut entry = index
+                .entry(&name, 0)
+                .ok_or_else(|| GitError::PathNotInIndex(name.clone()))?
+                .clone();
+            if let Some(assume_unchanged) = options.assume_unchanged {
+                entry.set_assume_valid(assume_unchanged);
+            }
+            if let Some(skip_worktree) = options.skip_worktree {
+                entry.set_skip_worktree(skip_worktree);
+            }
+            index.push(entry);
+            continue;
+        }
+
+        if options.unresolve {
+            if !index.unresolve(&name) {
+                return Err(GitError::NothingToUnresolve(name));
+            }
+            continue;
+        }
+
+        let path = repository.require_work_tree()?.join(&name);
+        update_index_path(&repository, &mut index, &path, name.clone(), &options)?;
+
+        if let Some(executable) = options.chmod {
+            let mut entry = index
+                .entry(&name, 0)
+                .ok_or_else(|| GitError::PathNotInIndex(name.clone()))?
+                .clone();
+            entry.set_mode(match entry.mode() {
+                0o100644 | 0o100755 if executable => 0o100755,
+                0o100644 | 0o100755 => 0o100644,
+                mode => return Err(GitError::InvalidFileMode(mode)),
+            });
+            index.push(entry);
+        }
+    }
+
+    if options.index_info {
+        let separator = if options.zero_terminated {
+            b'\0'
+        } else {
+            b'\n'
+        };
+        for line in io::stdin().lock().split(separator) {
+            let line = String::from_utf8(line?).map_err(|error| {
+                GitError::InvalidIndexInfo(String::from_utf8_lossy(error.as_bytes()).into_owned())
+            })?;
+            if line.is_empty() {
+                continue;
+            }
+
+            let (mode, oid, stage, name) = parse_index_info(&line)?;
+            verify_path(&name)?;
+            if mode == 0 {
+                index.remove(&name);
+            } else {
+                index.push(IndexEntry::without_stat(
+                    normalize_mode(mode)?,
+                    oid,
+                    stage,
+                    name,
+                ));
+            }
+        }
+    }
+
+    repository.write_index(&mut index)?;
+
+    Ok(up_to_date)
+}
+
+/// Updates the entry of a file from the working tree, removing it if the
+/// file is missing
+fn update_index_path(
+    repository: &Repository,
+    index: &mut Index,
+    path: &Path,
+    name: String,
+    options: &UpdateIndexOptions,
+) -> GitResult<()> {
+    if options.force_remove {
+        index.remove(&name);
+        return Ok(());
+    }
+
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error)
+            if matches!(
+                error.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+            ) =>
+        {
+            if !options.remove {
+                return Err(GitError::MissingRemoveOption(name));
+            }
+            index.remove(&name);
+            return Ok(());
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    if metadata.is_dir() {
+        return Err(GitError::PathIsDirectory(name));
+    }
+    if !options.add && !index.contains(&name) {
+        return Err(GitError::MissingAddOption(name));
+    }
+
+    let content = if metadata.is_symlink() {
+        fs::read_link(path)?.into_os_string().into_encoded_bytes()
+    } else {
+        fs::read(path)?
     };
+    let blob_id = repository.write_blob(content)?;
+
+    index.push(IndexEntry::new(metadata, blob_id, false, 0, name)?);
+
+    Ok(())
+}
+
+pub struct LsFilesOptions {
+    pub cached: bool,
+    /// Show mode, object id and stage of each entry
+    pub stage: bool,
+    pub deleted: bool,
+    pub modified: bool,
+    /// Show files in the working tree that are not in the index
+    pub others: bool,
+    /// Show only entries with a stage other than zero
+    pub unmerged: bool,
+    /// Show the cached stat data of each entry
+    pub debug: bool,
+    /// Terminate lines with a null byte instead of a line feed
+    pub zero_terminated: bool,
+}
+
+/// Lists the entries under the current directory, relative to it
+pub fn ls_files(mut options: LsFilesOptions) -> GitResult<()> {
+    let repository = Repository::discover()?;
+    let work_tree = repository.require_work_tree()?;
+    let prefix = repository.prefix();
+    let index = repository.index()?;
+
+    if options.unmerged {
+        options.stage = true;
+    }
+    if !(options.stage || options.deleted || options.modified || options.others) {
+        options.cached = true;
+    }
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    if options.others {
+        let mut tracked = index.entries().iter().map(|entry| entry.name()).peekable();
+        for file in repository.working_tree_files()? {
+            while tracked.next_if(|&name| name < file.as_str()).is_some() {}
+            if tracked.peek() != Some(&file.as_str()) {
+                let Some(file) = file.strip_prefix(prefix) else {
+                    continue;
+                };
+                write!(stdout, "{file}")?;
+                stdout.write_all(if options.zero_terminated {
+                    b"\0"
+                } else {
+                    b"\n"
+                })?;
+            }
+        }
+    }
+
+    let entries = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.name().starts_with(prefix))
+        .filter(|entry| !options.unmerged || entry.stage() != 0);
 
-    index.push(entry);
+    for entry in entries {
+        if options.cached || options.stage {
+            ls_files_entry(&mut stdout, entry, prefix, &options)?;
+        }
 
-    index.serialize_to_path(index_path)?;
+        if options.deleted || options.modified {
+            let status = index.entry_status(work_tree, entry)?;
+            if options.deleted && status == EntryStatus::Deleted {
+                ls_files_entry(&mut stdout, entry, prefix, &options)?;
+            }
+            if options.modified && status != EntryStatus::Unchanged {
+                ls_files_entry(&mut stdout, entry, prefix, &options)?;
+            }
+        }
+    }
+
+    stdout.flush()?;
 
     Ok(())
 }
 
-pub fn write_tree() -> GitResult<()> {
-    let git_dir = get_git_dir();
+fn ls_files_entry<W: Write>(
+    writer: &mut W,
+    entry: &IndexEntry,
+    prefix: &str,
+    options: &LsFilesOptions,
+) -> GitResult<()> {
+    if options.stage {
+        write!(
+            writer,
+            "{:06o} {} {}\t",
+            entry.mode(),
+            entry.oid(),
+            entry.stage()
+        )?;
+    }
 
-    let index_path = git_dir.join("index");
-    let index = Index::deserialize_from_path(index_path)?;
+    write!(writer, "{}", &entry.name()[prefix.len()..])?;
+    writer.write_all(if options.zero_terminated {
+        b"\0"
+    } else {
+        b"\n"
+    })?;
 
-    let tree = Tree::new(
-        index
-            .entries()
-            .iter()
-            .map(|index_entry| {
-                TreeEntry::new(
-                    index_entry.mode(),
-                    index_entry.name().to_string(),
-                    index_entry.oid().clone(),
-                )
-                .expect("index entries are always valid")
-            })
-            .collect(),
-    );
+    if options.debug {
+        writeln!(writer, "  ctime: {}:{}", entry.ctime(), entry.ctime_nsec())?;
+        writeln!(writer, "  mtime: {}:{}", entry.mtime(), entry.mtime_nsec())?;
+        writeln!(writer, "  dev: {}\tino: {}", entry.dev(), entry.ino())?;
+        writeln!(writer, "  uid: {}\tgid: {}", entry.uid(), entry.gid())?;
+        // name length is not part of the flags git keeps in memory
+        let flags = entry.flags() & !0xFFF;
+        writeln!(writer, "  size: {}\tflags: {:x}", entry.size(), flags)?;
+    }
 
-    let tree_id = tree.hash();
-    let tree_path = create_object_path(&git_dir, &tree_id)?;
-    let tree_file = File::create(tree_path)?;
+    Ok(())
+}
 
-    tree.serialize(tree_file)?;
+pub fn write_tree(prefix: Option<String>) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    let mut index = repository.index()?;
+    let cache_tree = index.cache_tree().cloned();
+
+    let tree_id = repository.write_tree(&mut index, prefix.as_deref().unwrap_or_default())?;
+
+    if index.cache_tree() != cache_tree.as_ref() {
+        repository.write_index(&mut index)?;
+    }
 
     println!("{}", tree_id);
 
     Ok(())
 }
 
+pub struct LsTreeOptions {
+    /// Recurse into subtrees
+    pub recursive: bool,
+    /// Show trees even when recursing into them
+    pub show_trees: bool,
+    /// Show only trees
+    pub only_trees: bool,
+    pub name_only: bool,
+    /// Show the size of blobs
+    pub long: bool,
+}
+
+pub fn ls_tree(tree_ish: String, paths: Vec<String>, options: LsTreeOptions) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    let oid = repository.resolve_revision(&tree_ish)?;
+    let tree = repository.read_tree(&oid)?;
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    ls_tree_entries(&repository, &tree, "", &paths, &options, &mut stdout)?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+/// Prints the entries of a tree whose path is under `base`. An entry is shown
+/// if it matches a path filter, and trees are walked when recursing or when a
+/// filter points inside them
+fn ls_tree_entries<W: Write>(
+    repository: &Repository,
+    tree: &Tree,
+    base: &str,
+    filters: &[String],
+    options: &LsTreeOptions,
+    writer: &mut W,
+) -> GitResult<()> {
+    for entry in tree.entries() {
+        let path = format!("{base}{}", entry.name());
+        let is_tree = entry.kind() == ObjectKind::Tree;
+
+        let matched = filters.is_empty()
+            || filters.iter().any(|filter| {
+                *filter == path
+                    || filter.ends_with('/') && path.starts_with(filter.as_str())
+                    || path.starts_with(&format!("{filter}/"))
+            });
+        let leads_to_filter = is_tree
+            && filters
+                .iter()
+                .any(|filter| filter.starts_with(&format!("{path}/")));
+
+        if !matched && !leads_to_filter {
+            continue;
+        }
+
+        let descend = is_tree && (leads_to_filter || options.recursive);
+        let show = if descend {
+            options.show_trees || (options.only_trees && !leads_to_filter)
+        } else {
+            matched && (is_tree || !options.only_trees)
+        };
+
+        if show {
+            if options.name_only {
+                writeln!(writer, "{path}")?;
+            } else if options.long {
+                let size = match entry.kind() {
+                    ObjectKind::Blob => repository.read_object_header(entry.oid())?.1.to_string(),
+                    _ => "-".to_string(),
+                };
+                writeln!(
+                    writer,
+                    "{:06o} {} {} {:>7}\t{}",
+                    entry.mode(),
+                    entry.kind(),
+                    entry.oid(),
+                    size,
+                    path
+                )?;
+            } else {
+                writeln!(
+                    writer,
+                    "{:06o} {} {}\t{}",
+                    entry.mode(),
+                    entry.kind(),
+                    entry.oid(),
+                    path
+                )?;
+            }
+        }
+
+        if descend {
+            let subtree = repository.read_tree(entry.oid())?;
+            ls_tree_entries(
+                repository,
+                &subtree,
+                &format!("{path}/"),
+                filters,
+                options,
+                writer,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn commit_tree(tree_id: String, parent: Option<String>, message: String) -> GitResult<()> {
-    let git_dir = get_git_dir();
+    let repository = Repository::discover()?;
 
     let mut parents = Vec::new();
 
@@ -145,21 +625,11 @@ pub fn commit_tree(tree_id: String, parent: Option<String>, message: String) ->
         parents.push(Oid::new(parent)?)
     };
 
-    let commit = Commit::new(
-        parents,
-        Oid::new(tree_id)?,
-        message.to_string(),
-        "John Doe".to_string(),
-        "johndoe@mail.com".to_string(),
-        "John Doe".to_string(),
-        "johndoe@mail.com".to_string(),
-    )?;
+    let author = repository.author()?;
+    let commiter = repository.committer()?;
 
-    let commit_id = commit.hash();
-    let commit_path = create_object_path(&git_dir, &commit_id)?;
-    let commit_file = File::create(commit_path)?;
-
-    commit.serialize(commit_file)?;
+    let commit_id =
+        repository.commit_tree(Oid::new(tree_id)?, parents, message, author, commiter)?;
 
     println!("{}", commit_id);
 
@@ -169,10 +639,673 @@ pub fn commit_tree(tree_id: String, parent: Option<String>, message: String) ->
 pub fn update_ref(hash: String) -> GitResult<()> {
     let oid = Oid::new(hash)?;
 
-    let git_dir = get_git_dir();
-    let reference_path = get_reference_path(&git_dir, DEFAULT_BRANCH);
+    let repository = Repository::discover()?;
 
-    write(reference_path, oid.as_bytes())?;
+    repository.update_reference("HEAD", &oid)
+}
+
+pub enum SymbolicRefAction {
+    /// Print the target, shortened to a branch name if `short`, and followed
+    /// down to the reference storing an object id if `recurse`
+    Read {
+        short: bool,
+        recurse: bool,
+    },
+    Set {
+        target: String,
+    },
+    Delete,
+}
+
+/// Reads, changes or deletes a symbolic reference such as `HEAD`. Returns
+/// false instead of failing when it is not symbolic and `quiet` is set
+pub fn symbolic_ref(name: String, action: SymbolicRefAction, quiet: bool) -> GitResult<bool> {
+    let repository = Repository::discover()?;
+
+    let target = match &action {
+        SymbolicRefAction::Set { target } => {
+            repository.set_symbolic_reference(&name, target)?;
+            return Ok(true);
+        }
+        // a repository without `HEAD` would no longer be recognized
+        SymbolicRefAction::Delete if name == "HEAD" => {
+            return Err(GitError::ProtectedReference(name));
+        }
+        _ => repository.symbolic_reference(&name)?,
+    };
+    let Some(target) = target else {
+        if quiet {
+            return Ok(false);
+        }
+        return Err(GitError::NotASymbolicReference(name));
+    };
+
+    if let SymbolicRefAction::Read { short, recurse } = action {
+        let target = if recurse {
+            repository.resolve_reference_name(&target)?
+        } else {
+            target
+        };
+        if short {
+            println!("{}", short_reference_name(&target));
+        } else {
+            println!("{target}");
+        }
+    } else {
+        repository.delete_reference(&name)?;
+    }
+
+    Ok(true)
+}
+
+/// Strips the directory of a reference, as in `master` for `refs/heads/master`
+fn short_reference_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Reads a tag object from stdin, validates it and stores it
+pub fn mktag() -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    let mut content = Vec::new();
+    io::stdin().read_to_end(&mut content)?;
+
+    let tag = Tag::deserialize_content(&content)?;
+    if tag.tagger().is_none() {
+        return Err(GitError::MalformedObject {
+            kind: ObjectKind::Tag,
+            reason: "missing tagger".to_string(),
+        });
+    }
+
+    let (kind, _) = repository.read_object_header(tag.object_id())?;
+    if kind != tag.object_kind() {
+        return Err(GitError::UnexpectedObjectKind {
+            expected: tag.object_kind(),
+            found: kind,
+        });
+    }
+
+    // the tag is stored as given, even where serializing it would differ
+    let tag_id = repository.write_object(&RawObject::new(ObjectKind::Tag, content))?;
+
+    println!("{}", tag_id);
+
+    Ok(())
+}
+
+/// Prints the name of every tag matching any of the patterns, or all of them if there are none
+pub fn tag_list(patterns: Vec<String>) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    for (reference, _) in repository.list_references("refs/tags")? {
+        let name = reference
+            .strip_prefix("refs/tags/")
+            .expect("listed references are always tags");
+
+        if patterns.is_empty() || patterns.iter().any(|pattern| wildmatch(pattern, name)) {
+            println!("{name}");
+        }
+    }
 
     Ok(())
 }
+
+/// Creates a tag pointing to the given revision, which is annotated if there is a message
+pub fn tag_create(
+    name: String,
+    revision: Option<String>,
+    message: Option<String>,
+    force: bool,
+) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    let reference = format!("refs/tags/{name}");
+    if !is_valid_reference_name(&reference) {
+        return Err(GitError::InvalidReferenceName(name));
+    }
+    if repository.reference_exists(&reference)? && !force {
+        return Err(GitError::ReferenceAlreadyExists(reference));
+    }
+
+    let object_id = repository.resolve_revision(revision.as_deref().unwrap_or("HEAD"))?;
+
+    let tag_id = match message {
+        Some(message) => {
+            let (object_kind, _) = repository.read_object_header(&object_id)?;
+            let tagger = repository.committer()?;
+            let tag = Tag::new(object_id, object_kind, name, tagger, message)?;
+
+            repository.write_tag(tag)?
+        }
+        None => object_id,
+    };
+
+    repository.update_reference(&reference, &tag_id)
+}
+
+pub fn tag_delete(name: String) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    let reference = format!("refs/tags/{name}");
+    let tag_id = repository.read_reference(&reference)?;
+
+    repository.delete_reference(&reference)?;
+
+    println!("Deleted tag '{}' (was {})", name, &tag_id[..7]);
+
+    Ok(())
+}
+
+/// Packs the objects listed on stdin, either one id per line or, with `revs`,
+/// revisions whose history is packed unless prefixed with `^`. The pack is
+/// written to stdout or to `<base_name>-<checksum>.pack` along with its index
+pub fn pack_objects(
+    base_name: Option<PathBuf>,
+    revs: bool,
+    options: DeltaOptions,
+) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    let mut oids = Vec::new();
+    let mut excluded = Vec::new();
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if !revs {
+            // anything after the id is a path hint, which is not needed
+            let id = line.split_once(' ').map_or(line, |(id, _)| id);
+            oids.push(Oid::new(id)?);
+        } else if let Some(revision) = line.strip_prefix('^') {
+            excluded.push(repository.resolve_revision(revision)?);
+        } else {
+            oids.push(repository.resolve_revision(line)?);
+        }
+    }
+
+    if revs {
+        oids = repository.reachable_objects(&oids, &excluded)?;
+    }
+
+    match base_name {
+        Some(base_name) => {
+            let checksum = repository.write_pack_files(&base_name, &oids, options)?;
+            println!("{checksum}");
+        }
+        None => {
+            let objects = oids
+                .into_iter()
+                .map(|oid| {
+                    let object = repository.read_raw_object(&oid)?;
+                    Ok((oid, object))
+                })
+                .collect::<GitResult<Vec<_>>>()?;
+
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            write_pack(objects, options, &mut stdout)?;
+            stdout.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Packs reachable objects, removing redundant packs and loose objects if `delete` is set
+pub fn repack(options: RepackOptions) -> GitResult<()> {
+    if Repository::discover()?.repack(options)?.is_none() {
+        println!("Nothing new to pack.");
+    }
+
+    Ok(())
+}
+
+/// Removes loose objects that are already packed, only listing them if `dry_run` is set
+pub fn prune_packed(dry_run: bool) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    for oid in repository.prune_packed(dry_run)? {
+        if dry_run {
+            println!("rm -f {}", repository.object_path(&oid).display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes unreachable loose objects older than `expire`, printing them if `dry_run` is set
+pub fn prune(expire: Option<String>, dry_run: bool) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    let Some(expire) = parse_expiry_date(expire.as_deref().unwrap_or("now"))? else {
+        return Ok(());
+    };
+
+    for oid in repository.prune(expire, dry_run)? {
+        if dry_run {
+            let (kind, _) = repository.read_object_header(&oid)?;
+            println!("{oid} {kind}");
+        }
+    }
+
+    Ok(())
+}
+
+pub fn pack_refs(all: bool, prune: bool) -> GitResult<()> {
+    Repository::discover()?.pack_references(all, prune)
+}
+
+/// Cleans up the repository: expires old reflog entries, packs references,
+/// repacks reachable objects into a single pack and prunes unreachable ones
+/// older than `prune_expire`. With `auto`, does nothing unless there are too
+/// many loose objects or packs
+pub fn gc(auto: bool, prune_expire: Option<String>) -> GitResult<()> {
+    let repository = Repository::discover()?;
+
+    if auto {
+        let threshold = match repository.config().get_int("gc.auto")? {
+            Some(threshold) => threshold.max(0) as usize,
+            None => GC_AUTO_THRESHOLD,
+        };
+        if !repository.needs_gc(threshold)? {
+            return Ok(());
+        }
+        eprintln!("Auto packing the repository for optimum performance.");
+    }
+
+    if let Some(expire) = parse_expiry_date(REFLOG_EXPIRE)? {
+        repository.expire_reflogs(expire)?;
+    }
+
+    repository.pack_references(true, true)?;
+
+    let prune_expire = parse_expiry_date(prune_expire.as_deref().unwrap_or(GC_PRUNE_EXPIRE))?;
+
+    repository.repack(RepackOptions {
+        all: true,
+        delete: true,
+        keep_unreachable: true,
+        // objects that are about to be pruned anyway are not worth loosening
+        unreachable_expire: prune_expire,
+        delta: DeltaOptions::default(),
+    })?;
+
+    if let Some(expire) = prune_expire {
+        repository.prune(expire, false)?;
+    }
+
+    Ok(())
+}
+
+/// Verifies the objects and connectivity of the repository, returning whether
+/// it is free of errors. Unreachable objects are only reported when no other
+/// object refers to them, or all of them if `unreachable` is set
+pub fn fsck(unreachable: bool, dangling: bool) -> GitResult<bool> {
+    let issues = Repository::discover()?.fsck()?;
+    let mut stdout = BufWriter::new(io::stdout().lock());
+
+    for issue in &issues {
+        match issue {
+            FsckIssue::Unreachable { kind, oid, .. } if unreachable => {
+                writeln!(stdout, "unreachable {kind} {oid}")?
+            }
+            FsckIssue::Unreachable { dangling: true, .. } if dangling => {
+                writeln!(stdout, "{issue}")?
+            }
+            FsckIssue::Unreachable { .. } => (),
+            FsckIssue::Malformed { .. }
+            | FsckIssue::BrokenLink { .. }
+            | FsckIssue::Missing { .. } => writeln!(stdout, "{issue}")?,
+            _ => eprintln!("{issue}"),
+        }
+    }
+
+    stdout.flush()?;
+
+    Ok(!issues.iter().any(FsckIssue::is_error))
+}
+
+pub enum ConfigAction {
+    /// Print the value of the key which takes precedence, among those
+    /// matching the pattern
+    Get {
+        key: String,
+        value_pattern: Option<String>,
+    },
+    GetAll {
+        key: String,
+        value_pattern: Option<String>,
+    },
+    /// Print the keys matching a regular expression, with their value
+    GetRegexp {
+        name_pattern: String,
+        value_pattern: Option<String>,
+    },
+    /// Set the value, replacing the single one matching the pattern
+    Set {
+        key: String,
+        value: String,
+        value_pattern: Option<String>,
+    },
+    ReplaceAll {
+        key: String,
+        value: String,
+        value_pattern: Option<String>,
+    },
+    Add {
+        key: String,
+        value: String,
+    },
+    Unset {
+        key: String,
+        value_pattern: Option<String>,
+    },
+    UnsetAll {
+        key: String,
+        value_pattern: Option<String>,
+    },
+    List,
+}
+
+/// The file `config` reads and writes, instead of the local configuration
+pub enum ConfigLocation {
+    Scope(ConfigScope),
+    File(PathBuf),
+}
+
+/// How values are checked and printed
+#[derive(Clone, Copy)]
+pub enum ConfigType {
+    Bool,
+    Int,
+    BoolOrInt,
+    /// Expand a leading `~/` to the home directory
+    Path,
+}
+
+pub struct ConfigOptions {
+    pub location: Option<ConfigLocation>,
+    pub value_type: Option<ConfigType>,
+    /// Terminate values with a null byte, and separate keys with a line feed
+    pub zero_terminated: bool,
+}
+
+/// Reads or edits the configuration, returning whether anything was found
+/// when reading or removing values
+pub fn config(action: ConfigAction, options: ConfigOptions) -> GitResult<bool> {
+    // the configuration of the repository is only needed for some scopes
+    let location = match RepositoryLocation::discover() {
+        Ok(location) => Some(location),
+        Err(GitError::NotARepository(_)) => None,
+        Err(error) => return Err(error),
+    };
+    let git_dir = location.as_ref().map(RepositoryLocation::git_dir);
+
+    match &action {
+        ConfigAction::Get { key, value_pattern } | ConfigAction::GetAll { key, value_pattern } => {
+            verify_key(key)?;
+            let config = read_config(git_dir, options.location.as_ref())?;
+            let pattern = value_pattern
+                .as_deref()
+                .map(ValuePattern::new)
+                .transpose()?;
+
+            let values: Vec<&ConfigEntry> = config
+                .get_all(key)
+                .filter(|entry| {
+                    pattern
+                        .as_ref()
+                        .is_none_or(|pattern| pattern.matches(entry.value()))
+                })
+                .collect();
+            let values = match values.last() {
+                Some(last) if !matches!(action, ConfigAction::GetAll { .. }) => vec![*last],
+                _ => values,
+            };
+
+            let mut stdout = io::stdout().lock();
+            for entry in &values {
+                let value = format_config_value(entry.key(), entry.value(), options.value_type)?;
+                write!(stdout, "{value}")?;
+                stdout.write_all(if options.zero_terminated {
+                    b"\0"
+                } else {
+                    b"\n"
+                })?;
+            }
+
+            Ok(!values.is_empty())
+        }
+        ConfigAction::GetRegexp {
+            name_pattern,
+            value_pattern,
+        } => {
+            let config = read_config(git_dir, options.location.as_ref())?;
+            // as with keys, the section and name are matched in lowercase
+            let regex = Regex::new(&canonical_key(name_pattern))
+                .map_err(|_| GitError::InvalidRegex(name_pattern.clone()))?;
+            let pattern = value_pattern
+                .as_deref()
+                .map(ValuePattern::new)
+                .transpose()?;
+
+            let mut found = false;
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            for entry in config.entries() {
+                if !regex.is_match(entry.key())
+                    || pattern
+                        .as_ref()
+                        .is_some_and(|pattern| !pattern.matches(entry.value()))
+                {
+                    continue;
+                }
+                found = true;
+
+                let separator = if options.zero_terminated { '\n' } else { ' ' };
+                write_config_entry(&mut stdout, entry, separator, &options)?;
+            }
+            stdout.flush()?;
+
+            Ok(found)
+        }
+        ConfigAction::List => {
+            let config = read_config(git_dir, options.location.as_ref())?;
+
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            for entry in config.entries() {
+                let separator = if options.zero_terminated { '\n' } else { '=' };
+                write_config_entry(&mut stdout, entry, separator, &options)?;
+            }
+            stdout.flush()?;
+
+            Ok(true)
+        }
+        ConfigAction::Set {
+            key,
+            value,
+            value_pattern,
+        }
+        | ConfigAction::ReplaceAll {
+            key,
+            value,
+            value_pattern,
+        } => {
+            let path = config_file_path(git_dir, options.location.as_ref())?;
+            let mut file = ConfigFile::read(&path)?;
+            let value = canonical_config_value(key, value, options.value_type)?;
+            let pattern = value_pattern
+                .as_deref()
+                .map(ValuePattern::new)
+                .transpose()?;
+
+            if matches!(action, ConfigAction::ReplaceAll { .. }) {
+                file.replace_all(key, &value, pattern.as_ref())?;
+            } else {
+                file.set(key, &value, pattern.as_ref())?;
+            }
+            file.write(&path)?;
+
+            Ok(true)
+        }
+        ConfigAction::Add { key, value } => {
+            let path = config_file_path(git_dir, options.location.as_ref())?;
+            let mut file = ConfigFile::read(&path)?;
+            let value = canonical_config_value(key, value, options.value_type)?;
+
+            file.add(key, &value)?;
+            file.write(&path)?;
+
+            Ok(true)
+        }
+        ConfigAction::Unset { key, value_pattern }
+        | ConfigAction::UnsetAll { key, value_pattern } => {
+            let path = config_file_path(git_dir, options.location.as_ref())?;
+            let mut file = ConfigFile::read(&path)?;
+            let pattern = value_pattern
+                .as_deref()
+                .map(ValuePattern::new)
+                .transpose()?;
+
+            let all = matches!(action, ConfigAction::UnsetAll { .. });
+            if !file.unset(key, pattern.as_ref(), all)? {
+                return Ok(false);
+            }
+            file.write(&path)?;
+
+            Ok(true)
+        }
+    }
+}
+
+/// Reads the given file, or the configuration of every scope
+fn read_config(git_dir: Option<&Path>, location: Option<&ConfigLocation>) -> GitResult<Config> {
+    let (path, scope) = match location {
+        None => return Config::load(git_dir),
+        Some(ConfigLocation::File(path)) => (path.clone(), ConfigScope::Local),
+        Some(&ConfigLocation::Scope(scope)) => {
+            let Some(path) = scope.path(git_dir) else {
+                return Ok(Config::default());
+            };
+            (path, scope)
+        }
+    };
+
+    let mut config = Config::default();
+    config.push_file(&ConfigFile::read(&path)?, scope);
+
+    Ok(config)
+}
+
+/// File edited for the given location, the local configuration by default
+fn config_file_path(
+    git_dir: Option<&Path>,
+    location: Option<&ConfigLocation>,
+) -> GitResult<PathBuf> {
+    let scope = match location {
+        Some(ConfigLocation::File(path)) => return Ok(path.clone()),
+        Some(&ConfigLocation::Scope(scope)) => scope,
+        None => ConfigScope::Local,
+    };
+
+    if matches!(scope, ConfigScope::Local | ConfigScope::Worktree) && git_dir.is_none() {
+        return Err(GitError::NotARepository(GIT_DIR.to_string()));
+    }
+
+    // without the extension, the worktree configuration is the local one
+    let scope = match scope {
+        ConfigScope::Worktree
+            if !Config::load(git_dir)?
+                .get_bool("extensions.worktreeConfig")?
+                .unwrap_or(false) =>
+        {
+            ConfigScope::Local
+        }
+        scope => scope,
+    };
+
+    scope.path(git_dir).ok_or_else(|| {
+        GitError::IO(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no configuration file",
+        ))
+    })
+}
+
+fn write_config_entry<W: Write>(
+    writer: &mut W,
+    entry: &ConfigEntry,
+    separator: char,
+    options: &ConfigOptions,
+) -> GitResult<()> {
+    write!(writer, "{}", entry.key())?;
+    if entry.value().is_some() || options.value_type.is_some() {
+        let value = format_config_value(entry.key(), entry.value(), options.value_type)?;
+        write!(writer, "{separator}{value}")?;
+    }
+    writer.write_all(if options.zero_terminated {
+        b"\0"
+    } else {
+        b"\n"
+    })?;
+
+    Ok(())
+}
+
+/// Formats a value read from the configuration as the given type
+fn format_config_value(
+    key: &str,
+    value: Option<&str>,
+    value_type: Option<ConfigType>,
+) -> GitResult<String> {
+    let invalid = |kind| GitError::InvalidConfigValue {
+        kind,
+        value: value.unwrap_or_default().to_string(),
+        key: key.to_string(),
+    };
+
+    let formatted = match value_type {
+        None => value.unwrap_or_default().to_string(),
+        Some(ConfigType::Bool) => parse_bool(value)
+            .ok_or_else(|| invalid("boolean"))?
+            .to_string(),
+        Some(ConfigType::Int) => value
+            .and_then(parse_int)
+            .ok_or_else(|| invalid("numeric"))?
+            .to_string(),
+        Some(ConfigType::BoolOrInt) => match value.and_then(parse_int) {
+            Some(number) => number.to_string(),
+            None => parse_bool(value)
+                .ok_or_else(|| invalid("boolean"))?
+                .to_string(),
+        },
+        Some(ConfigType::Path) => {
+            let value = value.unwrap_or_default();
+            match (value.strip_prefix("~/"), env::var("HOME")) {
+                (Some(path), Ok(home)) => Path::new(&home).join(path).display().to_string(),
+                _ => value.to_string(),
+            }
+        }
+    };
+
+    Ok(formatted)
+}
+
+/// Checks a value about to be written against its type, normalizing
+/// booleans and numbers as git does
+fn canonical_config_value(
+    key: &str,
+    value: &str,
+    value_type: Option<ConfigType>,
+) -> GitResult<String> {
+    match value_type {
+        None | Some(ConfigType::Path) => Ok(value.to_string()),
+        value_type => format_config_value(key, Some(value), value_type),
+    }
+}
@@ -0,0 +1,858 @@
+use std::{
+    env,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{
+    repository::{GitError, GitResult},
+    utils::write_locked,
+};
+
+/// path of the system configuration, replacing `/etc/gritconfig`
+const SYSTEM_CONFIG_ENV: &str = "GRIT_CONFIG_SYSTEM";
+/// path of the global configuration, replacing `~/.gritconfig`
+const GLOBAL_CONFIG_ENV: &str = "GRIT_CONFIG_GLOBAL";
+/// skips the system configuration when set
+const NO_SYSTEM_CONFIG_ENV: &str = "GRIT_CONFIG_NOSYSTEM";
+const SYSTEM_CONFIG: &str = "/etc/gritconfig";
+const GLOBAL_CONFIG: &str = ".gritconfig";
+const LOCAL_CONFIG: &str = "config";
+/// only read when `extensions.worktreeConfig` is enabled
+const WORKTREE_CONFIG: &str = "config.worktree";
+
+/// The files configuration is read from, from the lowest to the highest
+/// precedence
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    Worktree,
+}
+
+impl ConfigScope {
+    /// Path of the file of the scope, if it has one. Repository scopes need
+    /// the git dir
+    pub fn path(self, git_dir: Option<&Path>) -> Option<PathBuf> {
+        match self {
+            ConfigScope::System if env::var_os(NO_SYSTEM_CONFIG_ENV).is_some() => None,
+            ConfigScope::System => Some(
+                env::var_os(SYSTEM_CONFIG_ENV)
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from(SYSTEM_CONFIG)),
+            ),
+            ConfigScope::Global => env::var_os(GLOBAL_CONFIG_ENV)
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(GLOBAL_CONFIG))),
+            ConfigScope::Local => git_dir.map(|git_dir| git_dir.join(LOCAL_CONFIG)),
+            ConfigScope::Worktree => git_dir.map(|git_dir| git_dir.join(WORKTREE_CONFIG)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConfigEntry {
+    /// canonical key, with the section and name in lowercase
+    key: String,
+    /// `None` for a name without `=`, which is a true boolean
+    value: Option<String>,
+    scope: ConfigScope,
+}
+
+impl ConfigEntry {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    pub fn scope(&self) -> ConfigScope {
+        self.scope
+    }
+}
+
+/// The configuration of every scope merged together, where later entries
+/// override earlier ones
+#[derive(Clone, Default, Debug)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    /// Reads the system and global configuration, along with the one of the
+    /// repository if there is a git dir
+    pub fn load(git_dir: Option<&Path>) -> GitResult<Self> {
+        let mut config = Config::default();
+
+        for scope in [
+            ConfigScope::System,
+            ConfigScope::Global,
+            ConfigScope::Local,
+            ConfigScope::Worktree,
+        ] {
+            if scope == ConfigScope::Worktree
+                && !config
+                    .get_bool("extensions.worktreeConfig")?
+                    .unwrap_or(false)
+            {
+                continue;
+            }
+            let Some(path) = scope.path(git_dir) else {
+                continue;
+            };
+            config.push_file(&ConfigFile::read(&path)?, scope);
+        }
+
+        Ok(config)
+    }
+
+    /// Adds the entries of a file, overriding the existing ones
+    pub fn push_file(&mut self, file: &ConfigFile, scope: ConfigScope) {
+        self.entries
+            .extend(file.entries().map(|(key, value)| ConfigEntry {
+                key,
+                value: value.map(str::to_string),
+                scope,
+            }));
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    /// Every entry of the key, from the lowest to the highest precedence
+    pub fn get_all<'a>(&'a self, key: &str) -> impl Iterator<Item = &'a ConfigEntry> + 'a {
+        let key = canonical_key(key);
+        self.entries.iter().filter(move |entry| entry.key == key)
+    }
+
+    /// The entry of the key which takes precedence
+    pub fn get(&self, key: &str) -> Option<&ConfigEntry> {
+        self.get_all(key).last()
+    }
+
+    pub fn get_string(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(ConfigEntry::value)
+    }
+
+    pub fn get_bool(&self, key: &str) -> GitResult<Option<bool>> {
+        self.get(key)
+            .map(|entry| parse_bool(entry.value()).ok_or_else(|| invalid_value("boolean", entry)))
+            .transpose()
+    }
+
+    pub fn get_int(&self, key: &str) -> GitResult<Option<i64>> {
+        self.get(key)
+            .map(|entry| {
+                entry
+                    .value()
+                    .and_then(parse_int)
+                    .ok_or_else(|| invalid_value("numeric", entry))
+            })
+            .transpose()
+    }
+}
+
+fn invalid_value(kind: &'static str, entry: &ConfigEntry) -> GitError {
+    GitError::InvalidConfigValue {
+        kind,
+        value: entry.value().unwrap_or_default().to_string(),
+        key: entry.key.clone(),
+    }
+}
+
+/// Parses a boolean as git does, where a name without a value is true
+pub fn parse_bool(value: Option<&str>) -> Option<bool> {
+    let Some(value) = value else {
+        return Some(true);
+    };
+
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses an integer with an optional `k`, `m` or `g` suffix, each
+/// multiplying it by 1024 once more
+pub fn parse_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (number, factor) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1 << 10),
+        'm' => (&value[..value.len() - 1], 1 << 20),
+        'g' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+
+    number.parse::<i64>().ok()?.checked_mul(factor)
+}
+
+/// Lowercases the section and name of a key, keeping the subsection, without
+/// validating it
+pub fn canonical_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) => format!(
+            "{}{}{}",
+            key[..first].to_ascii_lowercase(),
+            &key[first..last],
+            key[last..].to_ascii_lowercase()
+        ),
+        _ => key.to_ascii_lowercase(),
+    }
+}
+
+/// Checks that a key has a section and a valid name
+pub fn verify_key(key: &str) -> GitResult<()> {
+    parse_key(key).map(|_| ())
+}
+
+/// Splits a key into its section, subsection and name, as given
+fn parse_key(key: &str) -> GitResult<(&str, Option<&str>, &str)> {
+    let (Some(first), Some(last)) = (key.find('.'), key.rfind('.')) else {
+        return Err(GitError::ConfigKeyWithoutSection(key.to_string()));
+    };
+
+    let section = &key[..first];
+    let name = &key[last + 1..];
+    let subsection = (first != last).then(|| &key[first + 1..last]);
+
+    let valid_section = !section.is_empty()
+        && section
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid_section || !is_valid_name(name) {
+        return Err(GitError::InvalidConfigKey(key.to_string()));
+    }
+
+    Ok((section, subsection, name))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Selects values with a regular expression, or those not matching it when
+/// prefixed with `!`
+#[derive(Clone, Debug)]
+pub struct ValuePattern {
+    regex: Regex,
+    negated: bool,
+}
+
+impl ValuePattern {
+    pub fn new(pattern: &str) -> GitResult<Self> {
+        let (pattern, negated) = match pattern.strip_prefix('!') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+        let regex = Regex::new(pattern).map_err(|_| GitError::InvalidRegex(pattern.to_string()))?;
+
+        Ok(Self { regex, negated })
+    }
+
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        self.regex.is_match(value.unwrap_or_default()) != self.negated
+    }
+}
+
+/// A single configuration file, which keeps its text so that editing it
+/// preserves comments and formatting
+#[derive(Clone, Default, Debug)]
+pub struct ConfigFile {
+    /// the file is the concatenation of the text of its items
+    items: Vec<ConfigItem>,
+}
+
+#[derive(Clone, Debug)]
+struct ConfigItem {
+    text: String,
+    kind: ItemKind,
+}
+
+#[derive(Clone, Debug)]
+enum ItemKind {
+    Section {
+        /// in lowercase
+        section: String,
+        subsection: Option<String>,
+    },
+    Entry {
+        /// in lowercase
+        name: String,
+        value: Option<String>,
+    },
+    /// blank lines and comments
+    Other,
+}
+
+impl ConfigFile {
+    /// Reads a file, which is empty if it does not exist
+    pub fn read(path: &Path) -> GitResult<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content, &path.display().to_string()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> GitResult<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_locked(path, self.to_string())?;
+
+        Ok(())
+    }
+
+    /// Parses the content of a file, whose name is only used in errors
+    pub fn parse(content: &str, origin: &str) -> GitResult<Self> {
+        let mut parser = Parser {
+            chars: content.chars().collect(),
+            position: 0,
+            line: 1,
+        };
+        let mut items = Vec::new();
+        let mut in_section = false;
+
+        while parser.position < parser.chars.len() {
+            let start = parser.position;
+            parser.skip_blanks();
+
+            let kind = match parser.peek() {
+                None | Some('\n' | '#' | ';') => {
+                    parser.skip_line();
+                    Some(ItemKind::Other)
+                }
+                Some('[') => {
+                    in_section = true;
+                    parser.parse_section()
+                }
+                Some(c) if c.is_ascii_alphabetic() && in_section => parser.parse_entry(),
+                _ => None,
+            }
+            .ok_or_else(|| GitError::InvalidConfig {
+                line: parser.error_line(),
+                path: origin.to_string(),
+            })?;
+
+            items.push(ConfigItem {
+                text: parser.chars[start..parser.position].iter().collect(),
+                kind,
+            });
+        }
+
+        Ok(Self { items })
+    }
+
+    /// Every entry as its canonical key and value, in order
+    pub fn entries(&self) -> impl Iterator<Item = (String, Option<&str>)> + '_ {
+        let mut prefix = String::new();
+
+        self.items.iter().filter_map(move |item| match &item.kind {
+            ItemKind::Section {
+                section,
+                subsection,
+            } => {
+                prefix = match subsection {
+                    Some(subsection) => format!("{section}.{subsection}."),
+                    None => format!("{section}."),
+                };
+                None
+            }
+            ItemKind::Entry { name, value } => Some((format!("{prefix}{name}"), value.as_deref())),
+            ItemKind::Other => None,
+        })
+    }
+
+    /// Positions of the items of the key whose value matches the pattern
+    fn find(&self, key: &str, pattern: Option<&ValuePattern>) -> GitResult<Vec<usize>> {
+        parse_key(key)?;
+        let key = canonical_key(key);
+
+        let mut prefix = String::new();
+        let mut positions = Vec::new();
+        for (position, item) in self.items.iter().enumerate() {
+            match &item.kind {
+                ItemKind::Section {
+                    section,
+                    subsection,
+                } => {
+                    prefix = match subsection {
+                        Some(subsection) => format!("{section}.{subsection}."),
+                        None => format!("{section}."),
+                    };
+                }
+                ItemKind::Entry { name, value }
+                    if format!("{prefix}{name}") == key
+                        && pattern.is_none_or(|pattern| pattern.matches(value.as_deref())) =>
+                {
+                    positions.push(position);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(positions)
+    }
+
+    /// Sets the value of the key, replacing the one matching the pattern if
+    /// any. Fails if several values would be replaced
+    pub fn set(&mut self, key: &str, value: &str, pattern: Option<&ValuePattern>) -> GitResult<()> {
+        match self.find(key, pattern)?[..] {
+            [] => self.add(key, value),
+            [position] => {
+                self.items[position] = entry_item(key, value)?;
+                Ok(())
+            }
+            _ => Err(GitError::MultipleConfigValues(canonical_key(key))),
+        }
+    }
+
+    /// Replaces every value of the key matching the pattern with a single one
+    pub fn replace_all(
+        &mut self,
+        key: &str,
+        value: &str,
+        pattern: Option<&ValuePattern>,
+    ) -> GitResult<()> {
+        let positions = self.find(key, pattern)?;
+        let Some((&first, rest)) = positions.split_first() else {
+            return self.add(key, value);
+        };
+
+        self.items[first] = entry_item(key, value)?;
+        for &position in rest.iter().rev() {
+            self.items.remove(position);
+        }
+
+        Ok(())
+    }
+
+    /// Adds a value to the key, after the last entry of its section, keeping
+    /// the existing ones
+    pub fn add(&mut self, key: &str, value: &str) -> GitResult<()> {
+        let (section, subsection, _) = parse_key(key)?;
+        let item = entry_item(key, value)?;
+        let section = section.to_ascii_lowercase();
+
+        let mut position = None;
+        let mut in_section = false;
+        for (index, current) in self.items.iter().enumerate() {
+            match &current.kind {
+                ItemKind::Section {
+                    section: current_section,
+                    subsection: current_subsection,
+                } => {
+                    in_section =
+                        *current_section == section && current_subsection.as_deref() == subsection;
+                    if in_section {
+                        position = Some(index + 1);
+                    }
+                }
+                ItemKind::Entry { .. } if in_section => position = Some(index + 1),
+                _ => (),
+            }
+        }
+
+        let (position, items) = match position {
+            Some(position) => (position, vec![item]),
+            None => {
+                let header = ConfigItem {
+                    text: section_header(&section, subsection),
+                    kind: ItemKind::Section {
+                        section,
+                        subsection: subsection.map(str::to_string),
+                    },
+                };
+                (self.items.len(), vec![header, item])
+            }
+        };
+
+        // the previous item may end the file without a newline
+        if let Some(previous) = position.checked_sub(1).map(|index| &mut self.items[index]) {
+            if !previous.text.ends_with('\n') {
+                previous.text.push('\n');
+            }
+        }
+        self.items.splice(position..position, items);
+
+        Ok(())
+    }
+
+    /// Removes the value of the key matching the pattern, or all of them,
+    /// returning whether there was any
+    pub fn unset(
+        &mut self,
+        key: &str,
+        pattern: Option<&ValuePattern>,
+        all: bool,
+    ) -> GitResult<bool> {
+        let positions = self.find(key, pattern)?;
+        if positions.len() > 1 && !all {
+            return Err(GitError::MultipleConfigValues(canonical_key(key)));
+        }
+
+        for &position in positions.iter().rev() {
+            self.items.remove(position);
+        }
+
+        Ok(!positions.is_empty())
+    }
+}
+
+impl Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            f.write_str(&item.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn section_header(section: &str, subsection: Option<&str>) -> String {
+    match subsection {
+        Some(subsection) => {
+            let subsection = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("[{section} \"{subsection}\"]\n")
+        }
+        None => format!("[{section}]\n"),
+    }
+}
+
+/// Formats an entry as git writes it, quoting values which would otherwise
+/// lose whitespace or be cut by a comment
+fn entry_item(key: &str, value: &str) -> GitResult<ConfigItem> {
+    let (_, _, name) = parse_key(key)?;
+
+    let quote = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains([';', '#']);
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c => escaped.push(c),
+        }
+    }
+    if quote {
+        escaped = format!("\"{escaped}\"");
+    }
+
+    Ok(ConfigItem {
+        text: format!("\t{name} = {escaped}\n"),
+        kind: ItemKind::Entry {
+            name: name.to_ascii_lowercase(),
+            value: Some(value.to_string()),
+        },
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    /// Line of the last character read, which may be the newline ending it
+    fn error_line(&self) -> usize {
+        match self
+            .position
+            .checked_sub(1)
+            .map(|position| self.chars[position])
+        {
+            Some('\n') => self.line - 1,
+            _ => self.line,
+        }
+    }
+
+    fn skip_blanks(&mut self) {
+        while self.peek().is_some_and(|c| c != '\n' && c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    /// Skips the rest of the line, including its newline
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    /// Parses `[section]`, `[section "subsection"]` or the deprecated
+    /// `[section.subsection]`. An entry may follow on the same line
+    fn parse_section(&mut self) -> Option<ItemKind> {
+        self.next();
+
+        let mut section = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+                break;
+            }
+            section.push(c.to_ascii_lowercase());
+            self.next();
+        }
+
+        let mut subsection = None;
+        if self.peek().is_some_and(char::is_whitespace) {
+            self.skip_blanks();
+            if self.next()? != '"' {
+                return None;
+            }
+            let mut name = String::new();
+            loop {
+                match self.next()? {
+                    '"' => break,
+                    '\n' => return None,
+                    // other escapes only drop the backslash
+                    '\\' => name.push(self.next().filter(|&c| c != '\n')?),
+                    c => name.push(c),
+                }
+            }
+            subsection = Some(name);
+        } else if let Some((name, rest)) = section.split_once('.') {
+            subsection = Some(rest.to_string());
+            section = name.to_string();
+        }
+
+        if section.is_empty() || self.next()? != ']' {
+            return None;
+        }
+
+        self.skip_blanks();
+        if matches!(self.peek(), None | Some('\n' | '#' | ';')) {
+            self.skip_line();
+        }
+
+        Some(ItemKind::Section {
+            section,
+            subsection,
+        })
+    }
+
+    /// Parses `name = value`, or a lone name
+    fn parse_entry(&mut self) -> Option<ItemKind> {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '-') {
+                break;
+            }
+            name.push(c.to_ascii_lowercase());
+            self.next();
+        }
+
+        self.skip_blanks();
+        let value = match self.peek() {
+            Some('=') => {
+                self.next();
+                Some(self.parse_value()?)
+            }
+            None | Some('\n' | '#' | ';') => {
+                self.skip_line();
+                None
+            }
+            _ => return None,
+        };
+
+        Some(ItemKind::Entry { name, value })
+    }
+
+    /// Parses a value up to the end of its line. Whitespace outside quotes is
+    /// trimmed, and each character of it within the value becomes a space. A
+    /// trailing backslash continues the value on the next line
+    fn parse_value(&mut self) -> Option<String> {
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut comment = false;
+        let mut spaces = 0;
+
+        loop {
+            let c = match self.next() {
+                None if quoted => return None,
+                None => return Some(value),
+                Some(c) => c,
+            };
+
+            if c == '\n' {
+                return if quoted { None } else { Some(value) };
+            }
+            if comment {
+                continue;
+            }
+            if c.is_whitespace() && !quoted {
+                if !value.is_empty() {
+                    spaces += 1;
+                }
+                continue;
+            }
+            if !quoted && (c == ';' || c == '#') {
+                comment = true;
+                continue;
+            }
+
+            for _ in 0..spaces {
+                value.push(' ');
+            }
+            spaces = 0;
+
+            match c {
+                '\\' => match self.next()? {
+                    '\n' => (),
+                    't' => value.push('\t'),
+                    'b' => value.push('\u{8}'),
+                    'n' => value.push('\n'),
+                    c @ ('\\' | '"') => value.push(c),
+                    _ => return None,
+                },
+                '"' => quoted = !quoted,
+                c => value.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    const CONTENT: &str = "\
+# comment
+[core]
+\tbare = false ; inline comment
+\tflag
+[remote \"Origin\"]  # comment
+\turl = \"  spaced \\\"quoted\\\"\"   path
+\tpush = a \\
+   b
+[old.Style] key = 1k
+";
+
+    #[test]
+    pub fn parses_values_and_keeps_the_text() {
+        let file = ConfigFile::parse(CONTENT, "config").unwrap();
+        assert_eq!(file.to_string(), CONTENT);
+
+        let entries: Vec<_> = file.entries().collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("core.bare".to_string(), Some("false")),
+                ("core.flag".to_string(), None),
+                (
+                    "remote.Origin.url".to_string(),
+                    Some("  spaced \"quoted\"   path")
+                ),
+                ("remote.Origin.push".to_string(), Some("a    b")),
+                ("old.style.key".to_string(), Some("1k")),
+            ]
+        );
+
+        let mut config = Config::default();
+        config.push_file(&file, ConfigScope::Local);
+        assert_eq!(config.get_bool("Core.Flag").unwrap(), Some(true));
+        assert_eq!(config.get_int("old.style.key").unwrap(), Some(1024));
+        assert!(config.get_int("core.bare").is_err());
+        assert_eq!(config.get_string("remote.origin.url"), None);
+
+        assert!(matches!(
+            ConfigFile::parse("[core]\n\tbare = \"false\n", "config"),
+            Err(GitError::InvalidConfig { line: 2, .. })
+        ));
+        assert!(ConfigFile::parse("key = value\n", "config").is_err());
+    }
+
+    #[test]
+    pub fn edits_preserve_the_rest_of_the_file() {
+        let mut file = ConfigFile::parse(CONTENT, "config").unwrap();
+
+        file.set("core.bare", "true", None).unwrap();
+        file.add("core.multi", "one").unwrap();
+        file.add("core.multi", " two;").unwrap();
+        assert!(matches!(
+            file.set("core.multi", "three", None),
+            Err(GitError::MultipleConfigValues(_))
+        ));
+        let pattern = ValuePattern::new("^one$").unwrap();
+        file.set("core.multi", "three", Some(&pattern)).unwrap();
+        file.set("new.Sub.Key", "value", None).unwrap();
+        assert!(file.unset("remote.Origin.push", None, false).unwrap());
+        assert!(!file.unset("remote.origin.url", None, true).unwrap());
+
+        assert_eq!(
+            file.to_string(),
+            "\
+# comment
+[core]
+\tbare = true
+\tflag
+\tmulti = three
+\tmulti = \" two;\"
+[remote \"Origin\"]  # comment
+\turl = \"  spaced \\\"quoted\\\"\"   path
+[old.Style] key = 1k
+[new \"Sub\"]
+\tKey = value
+"
+        );
+        assert!(matches!(
+            file.set("nosection", "value", None),
+            Err(GitError::ConfigKeyWithoutSection(_))
+        ));
+        assert!(matches!(
+            file.set("core.1name", "value", None),
+            Err(GitError::InvalidConfigKey(_))
+        ));
+    }
+
+    #[test]
+    pub fn value_patterns_follow_git() {
+        let pattern = ValuePattern::new("^core\\.(bare|file[a-z]+)$").unwrap();
+        assert!(pattern.matches(Some("core.filemode")));
+        assert!(!pattern.matches(Some("core.file")));
+
+        // `!` selects the values that do not match
+        let negated = ValuePattern::new("!^core\\.").unwrap();
+        assert!(negated.matches(Some("user.name")));
+        assert!(!negated.matches(Some("core.bare")));
+
+        // a key without a value matches as an empty string
+        assert!(ValuePattern::new("^$").unwrap().matches(None));
+        assert!(!ValuePattern::new("true").unwrap().matches(None));
+        assert!(ValuePattern::new("!true").unwrap().matches(None));
+
+        assert!(matches!(
+            ValuePattern::new("!(unclosed"),
+            Err(GitError::InvalidRegex(pattern)) if pattern == "(unclosed"
+        ));
+    }
+}
@@ -0,0 +1,516 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::File,
+    io::BufReader,
+    path::Path,
+};
+
+use crate::{
+    index::DEFAULT_INDEX_VERSION,
+    object::{normalize_mode, Object, ObjectKind, Oid, RawObject, Tree},
+    pack::Pack,
+    repository::{
+        get_object_path, list_loose_objects, list_pack_indexes, list_references_and_errors,
+        list_unreferenced_roots, read_index, resolve_head, GitError, GitResult,
+    },
+};
+
+/// A problem found while checking the repository, or an unreachable object
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum FsckIssue {
+    /// the content stored for an object does not hash to its id
+    HashMismatch {
+        oid: Oid,
+        location: String,
+    },
+    /// the object cannot be read at all
+    Corrupt {
+        oid: Oid,
+        reason: String,
+    },
+    /// the object can be read, but its content is invalid
+    Malformed {
+        kind: ObjectKind,
+        oid: Oid,
+        reason: String,
+        /// git itself accepts these, but does not create them
+        warning: bool,
+    },
+    /// a pack or its index is damaged
+    BadPack {
+        path: String,
+        reason: String,
+    },
+    /// a reference points to an object that does not exist
+    BadReference {
+        name: String,
+        oid: Oid,
+    },
+    /// a reference cannot be read
+    CorruptReference {
+        name: String,
+        reason: String,
+    },
+    /// an object refers to another that does not exist
+    BrokenLink {
+        from_kind: ObjectKind,
+        from: Oid,
+        to_kind: ObjectKind,
+        to: Oid,
+    },
+    Missing {
+        kind: ObjectKind,
+        oid: Oid,
+    },
+    /// an object not reachable from any reference, the index or the reflogs.
+    /// It is dangling if no other object refers to it either
+    Unreachable {
+        kind: ObjectKind,
+        oid: Oid,
+        dangling: bool,
+    },
+}
+
+impl FsckIssue {
+    /// Whether the issue means the repository is damaged
+    pub fn is_error(&self) -> bool {
+        match self {
+            FsckIssue::Malformed { warning, .. } => !warning,
+            FsckIssue::Unreachable { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+impl Display for FsckIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsckIssue::HashMismatch { oid, location } => {
+                write!(f, "error: hash mismatch for {location} (expected {oid})")
+            }
+            FsckIssue::Corrupt { oid, reason } => {
+                write!(f, "error: {oid}: object corrupt or missing: {reason}")
+            }
+            FsckIssue::Malformed {
+                kind,
+                oid,
+                reason,
+                warning,
+            } => {
+                let level = if *warning { "warning" } else { "error" };
+                write!(f, "{level} in {kind} {oid}: {reason}")
+            }
+            FsckIssue::BadPack { path, reason } => write!(f, "error: {path}: {reason}"),
+            FsckIssue::BadReference { name, oid } => {
+                write!(f, "error: {name}: invalid sha1 pointer {oid}")
+            }
+            FsckIssue::CorruptReference { name, reason } => {
+                write!(f, "error: {name}: invalid reference: {reason}")
+            }
+            FsckIssue::BrokenLink {
+                from_kind,
+                from,
+                to_kind,
+                to,
+            } => write!(
+                f,
+                "broken link from {from_kind:>6} {from}\n              to {to_kind:>6} {to}"
+            ),
+            FsckIssue::Missing { kind, oid } => write!(f, "missing {kind} {oid}"),
+            FsckIssue::Unreachable {
+                kind,
+                oid,
+                dangling,
+            } => {
+                let state = if *dangling { "dangling" } else { "unreachable" };
+                write!(f, "{state} {kind} {oid}")
+            }
+        }
+    }
+}
+
+/// Kind of a stored object and the objects it refers to
+struct StoredObject {
+    kind: ObjectKind,
+    links: Vec<(ObjectKind, Oid)>,
+}
+
+/// Checks every loose and packed object, rehashing it and validating its
+/// syntax, then walks from `HEAD`, the references, the index and the reflogs,
+/// reporting missing and unreachable objects
+pub fn fsck(git_dir: &Path) -> GitResult<Vec<FsckIssue>> {
+    let mut issues = Vec::new();
+    let mut objects: HashMap<Oid, StoredObject> = HashMap::new();
+
+    for oid in list_loose_objects(git_dir)? {
+        let object_path = get_object_path(git_dir, &oid);
+        let object = File::open(&object_path)
+            .map_err(GitError::from)
+            .and_then(|file| RawObject::deserialize(BufReader::new(file)));
+
+        match object {
+            Ok(object) if object.hash() != oid => issues.push(FsckIssue::HashMismatch {
+                oid,
+                location: object_path.display().to_string(),
+            }),
+            Ok(object) => {
+                let stored = check_object(&oid, object, &mut issues);
+                objects.insert(oid, stored);
+            }
+            Err(error) => issues.push(FsckIssue::Corrupt {
+                oid,
+                reason: error.to_string(),
+            }),
+        }
+    }
+
+    for index_path in list_pack_indexes(git_dir)? {
+        let bad_pack = |error: GitError| FsckIssue::BadPack {
+            path: index_path.display().to_string(),
+            reason: error.to_string(),
+        };
+        // a damaged pack only loses its own objects
+        let pack = match Pack::open(&index_path) {
+            Ok(pack) => pack,
+            Err(error) => {
+                issues.push(bad_pack(error));
+                continue;
+            }
+        };
+
+        if let Err(error) = pack.verify_checksum() {
+            issues.push(FsckIssue::BadPack {
+                path: pack.path().display().to_string(),
+                reason: error.to_string(),
+            });
+        }
+
+        let entries = match pack.index().entries() {
+            Ok(entries) => entries,
+            Err(error) => {
+                issues.push(bad_pack(error));
+                continue;
+            }
+        };
+        for (oid, offset, _) in entries {
+            if objects.contains_key(&oid) {
+                continue;
+            }
+
+            match pack.read_at(offset) {
+                Ok(object) if object.hash() != oid => issues.push(FsckIssue::HashMismatch {
+                    oid,
+                    location: pack.path().display().to_string(),
+                }),
+                Ok(object) => {
+                    let stored = check_object(&oid, object, &mut issues);
+                    objects.insert(oid, stored);
+                }
+                Err(error) => issues.push(FsckIssue::Corrupt {
+                    oid,
+                    reason: error.to_string(),
+                }),
+            }
+        }
+    }
+
+    let (mut references, broken) = list_references_and_errors(git_dir, "refs")?;
+    let mut broken: Vec<_> = broken
+        .into_iter()
+        .map(|(name, error)| (name, error.to_string()))
+        .collect();
+    match resolve_head(git_dir) {
+        Ok(head) => references.push(("HEAD".to_string(), head)),
+        Err(GitError::ReferenceNotFound(_)) => (),
+        Err(error) => broken.push(("HEAD".to_string(), error.to_string())),
+    }
+    issues.extend(
+        broken
+            .into_iter()
+            .map(|(name, reason)| FsckIssue::CorruptReference { name, reason }),
+    );
+    for (name, oid) in &references {
+        if !objects.contains_key(oid) {
+            issues.push(FsckIssue::BadReference {
+                name: name.clone(),
+                oid: oid.clone(),
+            });
+        }
+    }
+
+    let mut missing = HashSet::new();
+    for entry in read_index(git_dir, DEFAULT_INDEX_VERSION)?.entries() {
+        // submodule commits live in another repository
+        if entry.mode() != 0o160000 && !objects.contains_key(entry.oid()) {
+            missing.insert((ObjectKind::Blob, entry.oid().clone()));
+        }
+    }
+
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<Oid> = references.into_iter().map(|(_, oid)| oid).collect();
+    // objects that are not stored are skipped by the walk below
+    pending.extend(list_unreferenced_roots(git_dir, |_| Ok(true))?);
+    while let Some(oid) = pending.pop() {
+        if reachable.contains(&oid) {
+            continue;
+        }
+        let Some(object) = objects.get(&oid) else {
+            continue;
+        };
+
+        for (kind, link) in &object.links {
+            if let Some(linked) = objects.get(link) {
+                if linked.kind != *kind {
+                    issues.push(FsckIssue::Malformed {
+                        kind: object.kind,
+                        oid: oid.clone(),
+                        reason: format!("badType: {link} is a {}, not a {kind}", linked.kind),
+                        warning: false,
+                    });
+                }
+                pending.push(link.clone());
+            } else {
+                issues.push(FsckIssue::BrokenLink {
+                    from_kind: object.kind,
+                    from: oid.clone(),
+                    to_kind: *kind,
+                    to: link.clone(),
+                });
+                missing.insert((*kind, link.clone()));
+            }
+        }
+        reachable.insert(oid);
+    }
+
+    let mut missing: Vec<_> = missing.into_iter().collect();
+    missing.sort_by(|a, b| a.1.cmp(&b.1));
+    issues.extend(
+        missing
+            .into_iter()
+            .map(|(kind, oid)| FsckIssue::Missing { kind, oid }),
+    );
+
+    let referenced: HashSet<&Oid> = objects
+        .values()
+        .flat_map(|object| object.links.iter().map(|(_, link)| link))
+        .collect();
+
+    let mut unreachable: Vec<(&Oid, &StoredObject)> = objects
+        .iter()
+        .filter(|(oid, _)| !reachable.contains(*oid))
+        .collect();
+    unreachable.sort_by_key(|(oid, _)| *oid);
+
+    let unreachable = unreachable
+        .into_iter()
+        .map(|(oid, object)| FsckIssue::Unreachable {
+            kind: object.kind,
+            oid: oid.clone(),
+            dangling: !referenced.contains(oid),
+        })
+        .collect::<Vec<_>>();
+    issues.extend(unreachable);
+
+    Ok(issues)
+}
+
+/// Parses an object, reporting invalid content, and lists the objects it refers to
+fn check_object(oid: &Oid, object: RawObject, issues: &mut Vec<FsckIssue>) -> StoredObject {
+    let kind = object.kind();
+    let malformed = |reason: &str, warning: bool| FsckIssue::Malformed {
+        kind,
+        oid: oid.clone(),
+        reason: reason.to_string(),
+        warning,
+    };
+
+    let links = match Object::from_raw(object) {
+        Ok(Object::Blob(_)) => Vec::new(),
+        Ok(Object::Tree(tree)) => {
+            for (reason, warning) in check_tree(&tree) {
+                issues.push(malformed(reason, warning));
+            }
+            tree.entries()
+                .iter()
+                .filter(|entry| entry.kind() != ObjectKind::Commit)
+                .map(|entry| (entry.kind(), entry.oid().clone()))
+                .collect()
+        }
+        Ok(Object::Commit(commit)) => std::iter::once((ObjectKind::Tree, commit.tree_id().clone()))
+            .chain(
+                commit
+                    .parents()
+                    .iter()
+                    .map(|parent| (ObjectKind::Commit, parent.clone())),
+            )
+            .collect(),
+        Ok(Object::Tag(tag)) => vec![(tag.object_kind(), tag.object_id().clone())],
+        Err(error) => {
+            issues.push(malformed(&error.to_string(), false));
+            Vec::new()
+        }
+    };
+
+    StoredObject { kind, links }
+}
+
+/// Lists the problems of a tree, using the ids and messages of `git fsck`
+fn check_tree(tree: &Tree) -> Vec<(&'static str, bool)> {
+    let mut problems = Vec::new();
+
+    let mut names: Vec<&str> = tree.entries().iter().map(|entry| entry.name()).collect();
+    names.sort_unstable();
+    let has_duplicates = names.windows(2).any(|pair| pair[0] == pair[1]);
+
+    if has_duplicates {
+        problems.push(("duplicateEntries: contains duplicate file entries", false));
+    } else if !tree.is_canonical() {
+        problems.push(("treeNotSorted: not properly sorted", false));
+    }
+
+    let entries = tree.entries();
+    if entries
+        .iter()
+        .any(|entry| normalize_mode(entry.mode()).ok() != Some(entry.mode()))
+    {
+        problems.push(("badFilemode: contains bad file modes", true));
+    }
+    if entries.iter().any(|entry| entry.name().is_empty()) {
+        problems.push(("emptyName: contains empty pathname", true));
+    }
+    if entries.iter().any(|entry| entry.name().contains('/')) {
+        problems.push(("fullPathname: contains full pathnames", true));
+    }
+    if entries.iter().any(|entry| entry.name() == ".") {
+        problems.push(("hasDot: contains '.'", true));
+    }
+    if entries.iter().any(|entry| entry.name() == "..") {
+        problems.push(("hasDotdot: contains '..'", true));
+    }
+    if entries
+        .iter()
+        .any(|entry| entry.name().eq_ignore_ascii_case(".git"))
+    {
+        problems.push(("hasDotgit: contains '.git'", true));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+    use crate::repository::{write_object, PackCache};
+
+    #[test]
+    pub fn reports_unsorted_trees_and_bad_modes() {
+        let oid = Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap();
+
+        let mut content = Vec::new();
+        for (mode, name) in [("100644", "b"), ("100664", "a")] {
+            content.extend_from_slice(format!("{mode} {name}\0").as_bytes());
+            content.extend_from_slice(&oid.to_raw_bytes());
+        }
+        let tree = Tree::deserialize_content(&content).unwrap();
+
+        assert_eq!(
+            check_tree(&tree),
+            vec![
+                ("treeNotSorted: not properly sorted", false),
+                ("badFilemode: contains bad file modes", true),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn reports_corrupt_references_and_packs_and_keeps_going() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let git_dir = temp_dir.path();
+        std::fs::create_dir_all(git_dir.join("objects/pack")).unwrap();
+        std::fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(git_dir.join("refs/heads/bad"), "garbage\n").unwrap();
+        let oid = Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap();
+        std::fs::write(git_dir.join("refs/heads/gone"), format!("{oid}\n")).unwrap();
+        let index_path = git_dir.join("objects/pack/pack-bad.idx");
+        std::fs::write(&index_path, b"\xfftOc\0\0\0\x02").unwrap();
+        let pack_error = Pack::open(&index_path).err().unwrap();
+
+        let issues = fsck(git_dir).unwrap();
+
+        assert_eq!(
+            issues,
+            vec![
+                FsckIssue::BadPack {
+                    path: index_path.display().to_string(),
+                    reason: pack_error.to_string(),
+                },
+                FsckIssue::CorruptReference {
+                    name: "refs/heads/bad".to_string(),
+                    reason: GitError::InvalidOid.to_string(),
+                },
+                FsckIssue::BadReference {
+                    name: "refs/heads/gone".to_string(),
+                    oid,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    pub fn reports_links_to_objects_of_the_wrong_kind() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let git_dir = temp_dir.path();
+        std::fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        let packs = PackCache::default();
+        let write = |kind, content: Vec<u8>| {
+            write_object(git_dir, &packs, &RawObject::new(kind, content)).unwrap()
+        };
+
+        let blob = write(ObjectKind::Blob, b"content\n".to_vec());
+        let empty_tree = write(ObjectKind::Tree, Vec::new());
+        let mut tree_content = b"100644 file\0".to_vec();
+        tree_content.extend_from_slice(&empty_tree.to_raw_bytes());
+        let tree = write(ObjectKind::Tree, tree_content);
+        for (branch, tree_id) in [("blob", &blob), ("tree", &tree)] {
+            let commit = write(
+                ObjectKind::Commit,
+                format!(
+                    "tree {tree_id}\n\
+                    author A <a@b> 0 +0000\n\
+                    committer A <a@b> 0 +0000\n\
+                    \n\
+                    message\n"
+                )
+                .into_bytes(),
+            );
+            std::fs::write(
+                git_dir.join("refs/heads").join(branch),
+                format!("{commit}\n"),
+            )
+            .unwrap();
+        }
+
+        let mut issues: Vec<String> = fsck(git_dir)
+            .unwrap()
+            .iter()
+            .map(|issue| issue.to_string())
+            .filter(|issue| issue.contains("badType"))
+            .collect();
+        issues.sort();
+
+        let blob_commit = std::fs::read_to_string(git_dir.join("refs/heads/blob")).unwrap();
+        let mut expected = vec![
+            format!(
+                "error in commit {}: badType: {blob} is a blob, not a tree",
+                blob_commit.trim_end()
+            ),
+            format!("error in tree {tree}: badType: {empty_tree} is a tree, not a blob"),
+        ];
+        expected.sort();
+        assert_eq!(issues, expected);
+    }
+}
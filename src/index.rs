@@ -1,6 +1,6 @@
 use std::{
     fs::{File, Metadata},
-    io::{BufRead, BufReader, Write},
+    io::{self, BufRead, BufReader, Read, Write},
     os::unix::fs::MetadataExt,
     path::Path,
 };
@@ -8,94 +8,414 @@ use std::{
 use sha1::{Digest, Sha1};
 
 use crate::{
-    object::{Oid, RawOid},
-    repository::GitResult,
-    utils::extract_bits,
+    object::{normalize_mode, Oid, RawOid, OID_HEX_LEN},
+    repository::{GitError, GitResult},
+    utils::{extract_bits, read_offset_varint, write_offset_varint},
 };
 
+mod cache_tree;
+mod resolve_undo;
+mod stat;
+
+pub use cache_tree::CacheTree;
+pub use resolve_undo::ResolveUndoEntry;
+pub use stat::{EntryStatus, RefreshIssue, RefreshOptions};
+
 const INDEX_SIGNATURE: &str = "DIRC";
-const INDEX_VERSION: u32 = 2;
+/// signature, version and number of entries
+const HEADER_SIZE: usize = 12;
+
+pub const DEFAULT_INDEX_VERSION: u32 = 2;
+/// version 3 adds extended flags, version 4 compresses names against the previous one
+pub const SUPPORTED_INDEX_VERSIONS: [u32; 3] = [2, 3, 4];
+
+/// flags, stored after the regular ones when the extended bit is set
+const SKIP_WORKTREE_FLAG: u16 = 1 << 14;
+const INTENT_TO_ADD_FLAG: u16 = 1 << 13;
+
+const CACHE_TREE_SIGNATURE: &[u8; 4] = b"TREE";
+const RESOLVE_UNDO_SIGNATURE: &[u8; 4] = b"REUC";
+/// extensions describing the layout of the file, which become stale once it is rewritten
+const LAYOUT_SIGNATURES: [&[u8; 4]; 2] = [b"EOIE", b"IEOT"];
+
+/// Checks that a name can be stored in the index: a relative path without
+/// empty, `.`, `..` or `.git` components, which git refuses to check out
+pub fn verify_path(name: &str) -> GitResult<()> {
+    let is_valid = !name.is_empty()
+        && !name.contains('\0')
+        && name.split('/').all(|component| {
+            !matches!(component, "" | "." | "..") && !component.eq_ignore_ascii_case(".git")
+        });
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(GitError::InvalidPath(name.to_string()))
+    }
+}
+
+/// Parses a `--cacheinfo` argument, `<mode>,<oid>,<path>`
+pub fn parse_cacheinfo(cacheinfo: &str) -> GitResult<(u32, Oid, String)> {
+    let invalid = || GitError::InvalidCacheInfo(cacheinfo.to_string());
+
+    let mut fields = cacheinfo.splitn(3, ',');
+    let (Some(mode), Some(oid), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(invalid());
+    };
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| invalid())?;
+    let oid = Oid::new(oid)?;
 
-#[derive(Default, PartialEq, Eq, Debug)]
+    Ok((mode, oid, name.to_string()))
+}
+
+/// Parses an `--index-info` line, which is either `<mode> <oid>\t<path>`,
+/// `<mode> <oid> <stage>\t<path>` or `<mode> <type> <oid>\t<path>`
+pub fn parse_index_info(line: &str) -> GitResult<(u32, Oid, u8, String)> {
+    let invalid = || GitError::InvalidIndexInfo(line.to_string());
+
+    let (info, name) = line.split_once('\t').ok_or_else(invalid)?;
+    let fields: Vec<&str> = info.split(' ').collect();
+
+    let (mode, oid, stage) = match fields[..] {
+        [mode, oid] => (mode, oid, 0),
+        [mode, oid, stage] if matches!(stage, "0" | "1" | "2" | "3") => {
+            (mode, oid, stage.parse().map_err(|_| invalid())?)
+        }
+        [mode, _, oid] => (mode, oid, 0),
+        _ => return Err(invalid()),
+    };
+
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| invalid())?;
+    let oid = Oid::new(oid).map_err(|_| invalid())?;
+
+    Ok((mode, oid, stage, name.to_string()))
+}
+
+#[derive(PartialEq, Eq, Debug)]
 pub struct Index {
+    version: u32,
     entries: Vec<IndexEntry>,
+    cache_tree: Option<CacheTree>,
+    resolve_undo: Vec<ResolveUndoEntry>,
+    /// optional extensions grit does not understand, kept as read
+    unknown_extensions: Vec<([u8; 4], Vec<u8>)>,
+    /// modification time of the file, entries modified since may be racy
+    timestamp: Option<(i32, i32)>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
 }
 
 impl Index {
     pub fn new(mut entries: Vec<IndexEntry>) -> Self {
         entries.sort();
 
-        Self { entries }
+        Self {
+            version: DEFAULT_INDEX_VERSION,
+            entries,
+            cache_tree: None,
+            resolve_undo: Vec::new(),
+            unknown_extensions: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    /// Version the index is written with. Version 2 is upgraded to 3 when an
+    /// entry needs extended flags
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u32) -> GitResult<()> {
+        if !SUPPORTED_INDEX_VERSIONS.contains(&version) {
+            return Err(GitError::UnsupportedIndexVersion(version));
+        }
+        self.version = version;
+
+        Ok(())
     }
 
     pub fn entries(&self) -> &[IndexEntry] {
         &self.entries
     }
 
+    pub fn entry(&self, name: &str, stage: u8) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by(|entry| entry.name.as_str().cmp(name).then(entry.stage.cmp(&stage)))
+            .ok()
+            .map(|position| &self.entries[position])
+    }
+
+    /// Whether any stage of the path is in the index
+    pub fn contains(&self, name: &str) -> bool {
+        let position = self
+            .entries
+            .partition_point(|entry| entry.name.as_str() < name);
+        self.entries
+            .get(position)
+            .is_some_and(|entry| entry.name == name)
+    }
+
+    pub fn cache_tree(&self) -> Option<&CacheTree> {
+        self.cache_tree.as_ref()
+    }
+
+    pub fn set_cache_tree(&mut self, cache_tree: CacheTree) {
+        self.cache_tree = Some(cache_tree);
+    }
+
+    pub fn resolve_undo(&self) -> &[ResolveUndoEntry] {
+        &self.resolve_undo
+    }
+
+    /// Adds an entry, replacing the one with the same name and stage. A
+    /// resolved entry replaces all stages of a conflict, which are remembered
+    /// so that `unresolve` can restore them, while a conflicting stage
+    /// replaces the resolved entry
     pub fn push(&mut self, entry: IndexEntry) {
-        match self
+        let replaced: Vec<IndexEntry> = self
             .entries
-            .binary_search_by_key(&entry.name.as_str(), |entry| entry.name.as_str())
-        {
-            Ok(pos) => self.entries[pos] = entry,
-            Err(pos) => self.entries.insert(pos, entry),
+            .extract_if(.., |existing| {
+                existing.name == entry.name
+                    && (existing.stage == entry.stage || existing.stage == 0 || entry.stage == 0)
+            })
+            .collect();
+
+        if entry.stage == 0 && replaced.iter().any(|existing| existing.stage != 0) {
+            let mut stages = [None, None, None];
+            for existing in replaced.iter().filter(|existing| existing.stage != 0) {
+                stages[existing.stage as usize - 1] = Some((existing.mode, existing.oid.clone()));
+            }
+
+            self.resolve_undo
+                .retain(|resolved| resolved.name() != entry.name);
+            self.resolve_undo
+                .push(ResolveUndoEntry::new(entry.name.clone(), stages));
         }
+
+        self.invalidate(&entry.name);
+
+        let position = self.entries.partition_point(|existing| existing < &entry);
+        self.entries.insert(position, entry);
     }
 
-    pub fn serialize_to_path<P: AsRef<Path>>(&self, path: P) -> GitResult<()> {
-        let file = File::create(path)?;
-        self.serialize(file)
+    /// Removes every stage of an entry, returning whether there was any
+    pub fn remove(&mut self, name: &str) -> bool {
+        let length = self.entries.len();
+        self.entries.retain(|entry| entry.name != name);
+
+        if self.entries.len() == length {
+            return false;
+        }
+        self.invalidate(name);
+
+        true
     }
 
-    pub fn serialize<W: Write>(&self, mut writer: W) -> GitResult<()> {
-        let mut hasher = Sha1::new();
+    /// Restores the conflicting stages of a resolved entry, returning whether
+    /// they were remembered
+    pub fn unresolve(&mut self, name: &str) -> bool {
+        let Some(position) = self
+            .resolve_undo
+            .iter()
+            .position(|resolved| resolved.name() == name)
+        else {
+            return false;
+        };
+        let resolved = self.resolve_undo.remove(position);
+
+        self.remove(name);
+        for (stage, resolved_stage) in resolved.stages().iter().enumerate() {
+            if let Some((mode, oid)) = resolved_stage {
+                self.push(IndexEntry::without_stat(
+                    *mode,
+                    oid.clone(),
+                    stage as u8 + 1,
+                    name.to_string(),
+                ));
+            }
+        }
+
+        true
+    }
+
+    fn invalidate(&mut self, name: &str) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(name);
+        }
+    }
+
+    /// Writes the index, which then has the timestamp of the new file
+    pub fn serialize_to_path<P: AsRef<Path>>(&mut self, path: P) -> GitResult<()> {
+        let mut file = File::create(path)?;
+        self.serialize(&mut file)?;
+        self.timestamp = Some(file_timestamp(&file.metadata()?));
 
-        writer.write_all(INDEX_SIGNATURE.as_bytes())?;
-        hasher.write_all(INDEX_SIGNATURE.as_bytes())?;
+        Ok(())
+    }
 
-        writer.write_all(&INDEX_VERSION.to_be_bytes())?;
-        hasher.write_all(&INDEX_VERSION.to_be_bytes())?;
+    pub fn serialize<W: Write>(&self, mut writer: W) -> GitResult<()> {
+        let version = match self.version {
+            2 if self.entries.iter().any(IndexEntry::is_extended) => 3,
+            version => version,
+        };
 
-        writer.write_all(&(self.entries.len() as u32).to_be_bytes())?;
-        hasher.write_all(&(self.entries.len() as u32).to_be_bytes())?;
+        let mut content = Vec::new();
+        content.extend_from_slice(INDEX_SIGNATURE.as_bytes());
+        content.extend_from_slice(&version.to_be_bytes());
+        content.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
 
+        let mut previous_name = "";
         for entry in &self.entries {
-            entry.serialize(&mut writer)?;
-            entry.serialize(&mut hasher)?;
+            entry.serialize(&mut content, version, previous_name)?;
+            previous_name = &entry.name;
+        }
+
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut data = Vec::new();
+            cache_tree.serialize(&mut data)?;
+            write_extension(&mut content, CACHE_TREE_SIGNATURE, &data);
+        }
+
+        if !self.resolve_undo.is_empty() {
+            let mut data = Vec::new();
+            for resolved in &self.resolve_undo {
+                resolved.serialize(&mut data)?;
+            }
+            write_extension(&mut content, RESOLVE_UNDO_SIGNATURE, &data);
+        }
+
+        for (signature, data) in &self.unknown_extensions {
+            write_extension(&mut content, signature, data);
         }
 
-        let hash = hasher.finalize();
+        let hash = Sha1::digest(&content);
+        writer.write_all(&content)?;
         writer.write_all(&hash)?;
 
         Ok(())
     }
 
     pub fn deserialize_from_path<P: AsRef<Path>>(path: P) -> GitResult<Self> {
-        let file = BufReader::new(File::open(path)?);
-        Self::deserialize(file)
+        let file = File::open(path)?;
+        let timestamp = file_timestamp(&file.metadata()?);
+
+        let mut index = Self::deserialize(BufReader::new(file))?;
+        index.timestamp = Some(timestamp);
+
+        Ok(index)
     }
 
+    /// Reads an index, verifying its signature, version and trailing checksum
     pub fn deserialize<R: BufRead>(mut reader: R) -> GitResult<Self> {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+
+        if content.len() < HEADER_SIZE + OID_HEX_LEN {
+            return Err(GitError::CorruptIndex("file is too short".to_string()));
+        }
+        let (data, checksum) = content.split_at(content.len() - OID_HEX_LEN);
+
+        // an all zero checksum means it was not computed, as with `index.skipHash`
+        if checksum.iter().any(|&byte| byte != 0) && Sha1::digest(data).as_slice() != checksum {
+            return Err(GitError::IndexChecksumMismatch);
+        }
+
+        let mut data = data;
+
         let mut signature_bytes = [0; 4];
-        reader.read_exact(&mut signature_bytes)?;
+        data.read_exact(&mut signature_bytes)?;
+        if signature_bytes != INDEX_SIGNATURE.as_bytes() {
+            return Err(GitError::InvalidIndexSignature);
+        }
+
         let mut version_bytes = [0; 4];
-        reader.read_exact(&mut version_bytes)?;
-        let mut length_bytes = [0; 4];
-        reader.read_exact(&mut length_bytes)?;
+        data.read_exact(&mut version_bytes)?;
+        let version = u32::from_be_bytes(version_bytes);
+        if !SUPPORTED_INDEX_VERSIONS.contains(&version) {
+            return Err(GitError::UnsupportedIndexVersion(version));
+        }
 
+        let mut length_bytes = [0; 4];
+        data.read_exact(&mut length_bytes)?;
         let length = u32::from_be_bytes(length_bytes);
 
         let mut entries = Vec::with_capacity(length as usize);
 
+        let mut previous_name = String::new();
         for _ in 0..length {
-            let entry = IndexEntry::deserialize(&mut reader)?;
+            let entry =
+                IndexEntry::deserialize(&mut data, version, &previous_name).map_err(|error| {
+                    match error {
+                        GitError::IO(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                            GitError::CorruptIndex("truncated entry".to_string())
+                        }
+                        error => error,
+                    }
+                })?;
+            previous_name.clone_from(&entry.name);
             entries.push(entry);
         }
 
-        Ok(Index::new(entries))
+        let mut index = Index::new(entries);
+        index.version = version;
+
+        // extensions follow the entries, each as a signature, a size and data
+        while !data.is_empty() {
+            let mut signature = [0; 4];
+            let mut size_bytes = [0; 4];
+            data.read_exact(&mut signature)
+                .and_then(|_| data.read_exact(&mut size_bytes))
+                .map_err(|_| GitError::CorruptIndex("truncated extension".to_string()))?;
+
+            let size = u32::from_be_bytes(size_bytes) as usize;
+            if size > data.len() {
+                return Err(GitError::CorruptIndex("truncated extension".to_string()));
+            }
+            let (mut extension, rest) = data.split_at(size);
+            data = rest;
+
+            match &signature {
+                CACHE_TREE_SIGNATURE => {
+                    index.cache_tree = Some(CacheTree::deserialize(extension)?);
+                }
+                RESOLVE_UNDO_SIGNATURE => {
+                    while !extension.is_empty() {
+                        index
+                            .resolve_undo
+                            .push(ResolveUndoEntry::deserialize(&mut extension)?);
+                    }
+                }
+                signature if LAYOUT_SIGNATURES.contains(&signature) => (),
+                // extensions starting with an uppercase letter may be ignored
+                [b'A'..=b'Z', ..] => index
+                    .unknown_extensions
+                    .push((signature, extension.to_vec())),
+                _ => {
+                    return Err(GitError::UnsupportedIndexExtension(
+                        String::from_utf8_lossy(&signature).into_owned(),
+                    ))
+                }
+            }
+        }
+
+        Ok(index)
     }
 }
 
+fn file_timestamp(metadata: &Metadata) -> (i32, i32) {
+    (metadata.mtime() as i32, metadata.mtime_nsec() as i32)
+}
+
+fn write_extension(content: &mut Vec<u8>, signature: &[u8; 4], data: &[u8]) {
+    content.extend_from_slice(signature);
+    content.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    content.extend_from_slice(data);
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct IndexEntry {
     ctime: i32,
@@ -111,6 +431,9 @@ pub struct IndexEntry {
     oid: Oid,
     assume_valid: bool,
     stage: u8,
+    /// only stored in version 3 and later, like the flags below
+    skip_worktree: bool,
+    intent_to_add: bool,
     name: String,
 }
 
@@ -129,18 +452,51 @@ impl IndexEntry {
             mtime_nsec: metadata.mtime_nsec() as i32,
             dev: metadata.dev() as u32,
             ino: metadata.ino() as u32,
-            mode: metadata.mode(),
+            mode: normalize_mode(metadata.mode())?,
             uid: metadata.uid(),
             gid: metadata.gid(),
             size: metadata.size() as u32,
             oid,
             assume_valid,
             stage,
+            skip_worktree: false,
+            intent_to_add: false,
             name,
         })
     }
 
-    pub fn serialize<W: Write>(&self, mut writer: W) -> GitResult<()> {
+    /// Creates an entry without stat data, as for stages restored from the
+    /// resolve undo extension, which are never compared with the working tree
+    pub fn without_stat(mode: u32, oid: Oid, stage: u8, name: String) -> Self {
+        Self {
+            ctime: 0,
+            ctime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            oid,
+            assume_valid: false,
+            stage,
+            skip_worktree: false,
+            intent_to_add: false,
+            name,
+        }
+    }
+
+    /// Writes the entry in the format of the given index version. Version 4
+    /// stores the name as the number of bytes to remove from the end of
+    /// `previous_name` followed by the bytes to append, without padding
+    pub fn serialize<W: Write>(
+        &self,
+        mut writer: W,
+        version: u32,
+        previous_name: &str,
+    ) -> GitResult<()> {
         writer.write_all(&self.ctime.to_be_bytes())?;
         writer.write_all(&self.ctime_nsec.to_be_bytes())?;
         writer.write_all(&self.mtime.to_be_bytes())?;
@@ -154,28 +510,46 @@ impl IndexEntry {
 
         writer.write_all(&self.oid.to_raw_bytes())?;
 
-        let assume_valid_bit = (self.assume_valid as u16) << 15;
-        let extended_flag_bit = 0 << 14;
-        let stage_bits = (self.stage.min(0b11) as u16) << 12;
-        let name_length_as_u12 = self.name.len().min(0xFFF) as u16;
-        let flags = assume_valid_bit | extended_flag_bit | stage_bits | name_length_as_u12;
+        writer.write_all(&self.flags().to_be_bytes())?;
+        if self.is_extended() {
+            writer.write_all(&self.extended_flags().to_be_bytes())?;
+        }
+
+        if version >= 4 {
+            let common_length = previous_name
+                .bytes()
+                .zip(self.name.bytes())
+                .take_while(|(previous, current)| previous == current)
+                .count();
+
+            let mut prefix = Vec::new();
+            write_offset_varint(&mut prefix, (previous_name.len() - common_length) as u64);
+            writer.write_all(&prefix)?;
+            writer.write_all(&self.name.as_bytes()[common_length..])?;
+            writer.write_all(b"\0")?;
+
+            return Ok(());
+        }
 
-        writer.write_all(&flags.to_be_bytes())?;
         writer.write_all(self.name.as_bytes())?;
 
-        // entry size must be multiple of 8
+        // entry size must be multiple of 8, with at least one null byte
         // - first 10 fields occupy 4 bytes each: offset = 0
         // - hash always occupies 20 bytes: offset = 4
-        // - flags occupy 2 bytes: offset = 2
+        // - flags occupy 2 bytes, or 4 with extended flags: offset = 6 or 0
         // - name is variable length: offset = ?
-        let offset = (4 + 2 + self.name.len()) % 8;
+        let offset = (self.flags_size() + 4 + self.name.len()) % 8;
         let padding = vec![0; 8 - offset];
         writer.write_all(&padding)?;
 
         Ok(())
     }
 
-    pub fn deserialize<R: BufRead>(mut reader: R) -> GitResult<Self> {
+    pub fn deserialize<R: BufRead>(
+        mut reader: R,
+        version: u32,
+        previous_name: &str,
+    ) -> GitResult<Self> {
         let mut ctime_bytes = [0; 4];
         reader.read_exact(&mut ctime_bytes)?;
         let ctime = i32::from_be_bytes(ctime_bytes);
@@ -224,24 +598,52 @@ impl IndexEntry {
         reader.read_exact(&mut flags_bytes)?;
         let flags = u16::from_be_bytes(flags_bytes);
         let assume_valid = extract_bits(flags, 0b1000000000000000, 15) != 0;
+        let extended = extract_bits(flags, 0b100000000000000, 14) != 0;
         let stage = extract_bits(flags, 0b11000000000000, 12) as u8;
         let _name_length = extract_bits(flags, 0xFFF, 0);
 
+        let extended_flags = if extended {
+            if version < 3 {
+                return Err(GitError::CorruptIndex(
+                    "extended flags in a version 2 index".to_string(),
+                ));
+            }
+            let mut extended_flags_bytes = [0; 2];
+            reader.read_exact(&mut extended_flags_bytes)?;
+            u16::from_be_bytes(extended_flags_bytes)
+        } else {
+            0
+        };
+        let skip_worktree = extended_flags & SKIP_WORKTREE_FLAG != 0;
+        let intent_to_add = extended_flags & INTENT_TO_ADD_FLAG != 0;
+
         let mut name_bytes = Vec::new();
+        if version >= 4 {
+            let strip_length = read_offset_varint(&mut reader)? as usize;
+            let kept_length = previous_name
+                .len()
+                .checked_sub(strip_length)
+                .ok_or_else(|| GitError::CorruptIndex("invalid name prefix".to_string()))?;
+            name_bytes.extend_from_slice(&previous_name.as_bytes()[..kept_length]);
+        }
         reader.read_until(b'\0', &mut name_bytes)?;
         // the null terminator is read, so we remove it and take it into account when reading padding
-        name_bytes.pop();
-        let name = String::from_utf8(name_bytes).unwrap_or_default();
+        if name_bytes.pop() != Some(b'\0') {
+            return Err(GitError::CorruptIndex(
+                "unterminated entry name".to_string(),
+            ));
+        }
+        let name = String::from_utf8(name_bytes)
+            .map_err(|_| GitError::CorruptIndex("entry name is not utf8".to_string()))?;
 
-        // entry size must be multiple of 8
-        // - first 10 fields occupy 4 bytes each: offset = 0
-        // - hash always occupies 20 bytes: offset = 4
-        // - flags occupy 2 bytes: offset = 2
-        // - name is variable length: offset = ?
-        let offset = (4 + 2 + name.len()) % 8;
-        // we use 7 instead of 8 as we already read the string null terminator
-        let mut padding_bytes = vec![0; 7 - offset];
-        reader.read_exact(&mut padding_bytes)?;
+        if version < 4 {
+            // entry size must be multiple of 8, see `serialize`. We use 7
+            // instead of 8 as we already read the string null terminator
+            let flags_size = if extended { 4 } else { 2 };
+            let offset = (flags_size + 4 + name.len()) % 8;
+            let mut padding_bytes = vec![0; 7 - offset];
+            reader.read_exact(&mut padding_bytes)?;
+        }
 
         Ok(IndexEntry {
             ctime,
@@ -257,10 +659,51 @@ impl IndexEntry {
             oid,
             assume_valid,
             stage,
+            skip_worktree,
+            intent_to_add,
             name,
         })
     }
 
+    /// Whether the entry has flags that need the extended flags of version 3
+    pub fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+
+    /// Flags as stored on disk: assume valid bit, extended bit, stage and name length
+    pub fn flags(&self) -> u16 {
+        let assume_valid_bit = (self.assume_valid as u16) << 15;
+        let extended_flag_bit = (self.is_extended() as u16) << 14;
+        let stage_bits = (self.stage.min(0b11) as u16) << 12;
+        let name_length_as_u12 = self.name.len().min(0xFFF) as u16;
+
+        assume_valid_bit | extended_flag_bit | stage_bits | name_length_as_u12
+    }
+
+    /// Extended flags as stored on disk: skip worktree and intent to add bits
+    pub fn extended_flags(&self) -> u16 {
+        let skip_worktree_bit = if self.skip_worktree {
+            SKIP_WORKTREE_FLAG
+        } else {
+            0
+        };
+        let intent_to_add_bit = if self.intent_to_add {
+            INTENT_TO_ADD_FLAG
+        } else {
+            0
+        };
+
+        skip_worktree_bit | intent_to_add_bit
+    }
+
+    fn flags_size(&self) -> usize {
+        if self.is_extended() {
+            4
+        } else {
+            2
+        }
+    }
+
     pub fn ctime(&self) -> i32 {
         self.ctime
     }
@@ -282,6 +725,9 @@ impl IndexEntry {
     pub fn mode(&self) -> u32 {
         self.mode
     }
+    pub fn set_mode(&mut self, mode: u32) {
+        self.mode = mode;
+    }
     pub fn uid(&self) -> u32 {
         self.uid
     }
@@ -297,9 +743,21 @@ impl IndexEntry {
     pub fn assume_valid(&self) -> bool {
         self.assume_valid
     }
+    pub fn set_assume_valid(&mut self, assume_valid: bool) {
+        self.assume_valid = assume_valid;
+    }
     pub fn stage(&self) -> u8 {
         self.stage
     }
+    pub fn skip_worktree(&self) -> bool {
+        self.skip_worktree
+    }
+    pub fn set_skip_worktree(&mut self, skip_worktree: bool) {
+        self.skip_worktree = skip_worktree;
+    }
+    pub fn intent_to_add(&self) -> bool {
+        self.intent_to_add
+    }
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -344,6 +802,8 @@ mod tests {
                 oid: Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap(),
                 assume_valid: false,
                 stage: 0,
+                skip_worktree: false,
+                intent_to_add: false,
                 name: "name1".to_string(),
             },
             IndexEntry {
@@ -360,6 +820,8 @@ mod tests {
                 oid: Oid::new("554b0c91f951764bb11f1db849685d95b2c7a48f").unwrap(),
                 assume_valid: true,
                 stage: 1,
+                skip_worktree: false,
+                intent_to_add: false,
                 name: "name2".to_string(),
             },
             IndexEntry {
@@ -376,6 +838,8 @@ mod tests {
                 oid: Oid::new("bedc28ca5099946b354104a3c6cc90ec20dbcaec").unwrap(),
                 assume_valid: false,
                 stage: 2,
+                skip_worktree: false,
+                intent_to_add: false,
                 name: "name3".to_string(),
             },
         ];
@@ -389,4 +853,211 @@ mod tests {
 
         assert_eq!(index, deserialized);
     }
+
+    #[test]
+    pub fn rejects_corrupted_files() {
+        let index = Index::new(vec![IndexEntry {
+            ctime: 1234,
+            ctime_nsec: 1234,
+            mtime: 1234,
+            mtime_nsec: 1234,
+            dev: 1234,
+            ino: 1234,
+            mode: 1234,
+            uid: 1234,
+            gid: 1234,
+            size: 1234,
+            oid: Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap(),
+            assume_valid: false,
+            stage: 0,
+            skip_worktree: false,
+            intent_to_add: false,
+            name: "name".to_string(),
+        }]);
+
+        let mut serialized = Vec::new();
+        index.serialize(&mut serialized).unwrap();
+
+        let mut flipped = serialized.clone();
+        flipped[20] ^= 1;
+        assert!(matches!(
+            Index::deserialize(Cursor::new(flipped)),
+            Err(GitError::IndexChecksumMismatch)
+        ));
+
+        // a zeroed checksum is not verified, so the signature is checked next
+        let mut wrong_signature = serialized.clone();
+        wrong_signature[..4].copy_from_slice(b"CRID");
+        let checksum_start = wrong_signature.len() - OID_HEX_LEN;
+        wrong_signature[checksum_start..].fill(0);
+        assert!(matches!(
+            Index::deserialize(Cursor::new(wrong_signature)),
+            Err(GitError::InvalidIndexSignature)
+        ));
+
+        assert!(matches!(
+            Index::deserialize(Cursor::new(&serialized[..10])),
+            Err(GitError::CorruptIndex(_))
+        ));
+    }
+
+    #[test]
+    pub fn round_trips_extended_flags_and_compressed_names() {
+        let entry = |name: &str, skip_worktree: bool| IndexEntry {
+            ctime: 1234,
+            ctime_nsec: 1234,
+            mtime: 1234,
+            mtime_nsec: 1234,
+            dev: 1234,
+            ino: 1234,
+            mode: 0o100644,
+            uid: 1234,
+            gid: 1234,
+            size: 1234,
+            oid: Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap(),
+            assume_valid: false,
+            stage: 0,
+            skip_worktree,
+            intent_to_add: false,
+            name: name.to_string(),
+        };
+
+        for (version, expected_version) in [(2, 3), (3, 3), (4, 4)] {
+            let mut index = Index::new(vec![
+                entry("dir/file", false),
+                entry("dir/file.txt", true),
+                entry("dir/other", false),
+                entry("file", false),
+            ]);
+            index.set_version(version).unwrap();
+
+            let mut serialized = Vec::new();
+            index.serialize(&mut serialized).unwrap();
+            let deserialized = Index::deserialize(Cursor::new(serialized)).unwrap();
+
+            assert_eq!(deserialized.version(), expected_version);
+            assert_eq!(deserialized.entries(), index.entries());
+        }
+    }
+
+    #[test]
+    pub fn resolving_conflicts_can_be_undone() {
+        let ours = Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap();
+        let theirs = Oid::new("2e65efe2a145dda7ee51d1741299f848e5bf752e").unwrap();
+        let stage = |stage: u8, oid: &Oid| {
+            IndexEntry::without_stat(0o100644, oid.clone(), stage, "file".to_string())
+        };
+
+        let mut index = Index::new(vec![stage(2, &ours), stage(3, &theirs)]);
+        index.set_cache_tree(CacheTree::new(String::new(), 1, ours.clone(), Vec::new()));
+
+        index.push(stage(0, &theirs));
+        assert_eq!(index.entries(), &[stage(0, &theirs)]);
+        assert_eq!(index.cache_tree().unwrap().oid(), None);
+
+        let mut serialized = Vec::new();
+        index.serialize(&mut serialized).unwrap();
+        let mut index = Index::deserialize(Cursor::new(serialized)).unwrap();
+        assert_eq!(
+            index.resolve_undo(),
+            &[ResolveUndoEntry::new(
+                "file".to_string(),
+                [
+                    None,
+                    Some((0o100644, ours.clone())),
+                    Some((0o100644, theirs.clone()))
+                ]
+            )]
+        );
+
+        assert!(index.unresolve("file"));
+        assert_eq!(index.entries(), &[stage(2, &ours), stage(3, &theirs)]);
+        assert!(index.resolve_undo().is_empty());
+        assert!(!index.unresolve("file"));
+    }
+
+    #[test]
+    pub fn rejects_invalid_paths() {
+        for name in ["a", "dir/file", ".gitignore", "dir/.git-file", "..a"] {
+            assert!(verify_path(name).is_ok(), "{name}");
+        }
+
+        for name in [
+            "",
+            "/a",
+            "a/",
+            "a//b",
+            "./a",
+            "a/../b",
+            "..",
+            ".git/config",
+            "a/.GIT/b",
+        ] {
+            assert!(
+                matches!(verify_path(name), Err(GitError::InvalidPath(_))),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn parses_index_info() {
+        let oid = Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap();
+
+        assert_eq!(
+            parse_index_info(&format!("100644 {oid}\tdir/file")).unwrap(),
+            (0o100644, oid.clone(), 0, "dir/file".to_string())
+        );
+        assert_eq!(
+            parse_index_info(&format!("100755 {oid} 2\tfile")).unwrap(),
+            (0o100755, oid.clone(), 2, "file".to_string())
+        );
+        assert_eq!(
+            parse_index_info(&format!("100644 blob {oid}\tname with spaces")).unwrap(),
+            (0o100644, oid.clone(), 0, "name with spaces".to_string())
+        );
+        assert_eq!(
+            parse_index_info(&format!("0 {oid}\tfile")).unwrap(),
+            (0, oid.clone(), 0, "file".to_string())
+        );
+
+        for line in [
+            format!("100644 {oid} file"),
+            format!("100644 {oid} 4\tfile"),
+            format!("100644 blob {oid} 1\tfile"),
+            format!("100648 {oid}\tfile"),
+            "100644 1234\tfile".to_string(),
+            "\tfile".to_string(),
+        ] {
+            assert!(
+                matches!(parse_index_info(&line), Err(GitError::InvalidIndexInfo(_))),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn parses_cacheinfo() {
+        let oid = Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap();
+
+        assert_eq!(
+            parse_cacheinfo(&format!("100644,{oid},dir/file")).unwrap(),
+            (0o100644, oid.clone(), "dir/file".to_string())
+        );
+        assert_eq!(
+            parse_cacheinfo(&format!("120000,{oid},a,b")).unwrap(),
+            (0o120000, oid.clone(), "a,b".to_string())
+        );
+
+        for cacheinfo in [format!("100644,{oid}"), format!("10064x,{oid},file")] {
+            assert!(
+                matches!(
+                    parse_cacheinfo(&cacheinfo),
+                    Err(GitError::InvalidCacheInfo(_))
+                ),
+                "{cacheinfo}"
+            );
+        }
+        assert!(parse_cacheinfo("100644,1234,file").is_err());
+    }
 }
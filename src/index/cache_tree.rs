@@ -0,0 +1,184 @@
+use std::io::{BufRead, Write};
+
+use crate::{
+    object::{Oid, RawOid},
+    repository::{GitError, GitResult},
+};
+
+/// Node of the `TREE` extension, which caches the id of the tree written for
+/// each directory of the index so that unchanged directories need not be
+/// rehashed. Nodes are invalidated when an entry below them changes
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CacheTree {
+    /// last component of the directory path, empty for the root
+    name: String,
+    /// number of index entries covered by this directory, recursively
+    entry_count: usize,
+    /// id of the tree, unless it was invalidated
+    oid: Option<Oid>,
+    children: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    pub fn new(name: String, entry_count: usize, oid: Oid, children: Vec<CacheTree>) -> Self {
+        Self {
+            name,
+            entry_count,
+            oid: Some(oid),
+            children,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.entry_count
+    }
+
+    /// Id of the tree, or `None` if an entry below changed since it was written
+    pub fn oid(&self) -> Option<&Oid> {
+        self.oid.as_ref()
+    }
+
+    pub fn children(&self) -> &[CacheTree] {
+        &self.children
+    }
+
+    /// Finds the node of a directory, given as a path relative to this one
+    pub fn find(&self, path: &str) -> Option<&CacheTree> {
+        let path = path.trim_end_matches('/');
+        if path.is_empty() {
+            return Some(self);
+        }
+
+        let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+        self.children
+            .iter()
+            .find(|child| child.name == name)
+            .and_then(|child| child.find(rest))
+    }
+
+    /// Invalidates every directory containing the given file path
+    pub fn invalidate(&mut self, path: &str) {
+        self.oid = None;
+
+        if let Some((name, rest)) = path.split_once('/') {
+            if let Some(child) = self.children.iter_mut().find(|child| child.name == name) {
+                child.invalidate(rest);
+            }
+        }
+    }
+
+    /// Writes this node and then its children, as
+    /// `<name>\0<entry count> <children count>\n<id>`, where the count is -1
+    /// and the id is omitted for invalidated nodes
+    pub fn serialize<W: Write>(&self, mut writer: W) -> GitResult<()> {
+        self.serialize_node(&mut writer)
+    }
+
+    fn serialize_node(&self, writer: &mut dyn Write) -> GitResult<()> {
+        writer.write_all(self.name.as_bytes())?;
+        writer.write_all(b"\0")?;
+
+        match &self.oid {
+            Some(oid) => {
+                writeln!(writer, "{} {}", self.entry_count, self.children.len())?;
+                writer.write_all(&oid.to_raw_bytes())?;
+            }
+            None => writeln!(writer, "-1 {}", self.children.len())?,
+        }
+
+        for child in &self.children {
+            child.serialize_node(writer)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn deserialize<R: BufRead>(mut reader: R) -> GitResult<Self> {
+        Self::deserialize_node(&mut reader)
+    }
+
+    fn deserialize_node(reader: &mut dyn BufRead) -> GitResult<Self> {
+        let mut name = Vec::new();
+        reader.read_until(b'\0', &mut name)?;
+        if name.pop() != Some(b'\0') {
+            return Err(corrupt("unterminated path"));
+        }
+        let name = String::from_utf8(name).map_err(|_| corrupt("path is not utf8"))?;
+
+        let mut counts = Vec::new();
+        reader.read_until(b'\n', &mut counts)?;
+        if counts.pop() != Some(b'\n') {
+            return Err(corrupt("unterminated counts"));
+        }
+        let counts = String::from_utf8(counts).map_err(|_| corrupt("invalid counts"))?;
+        let (entry_count, children_count) = counts
+            .split_once(' ')
+            .ok_or_else(|| corrupt("invalid counts"))?;
+        let entry_count: i64 = entry_count
+            .parse()
+            .map_err(|_| corrupt("invalid entry count"))?;
+        let children_count: usize = children_count
+            .parse()
+            .map_err(|_| corrupt("invalid subtree count"))?;
+
+        let oid = if entry_count >= 0 {
+            let mut raw_oid = RawOid::default();
+            reader.read_exact(&mut raw_oid)?;
+            Some(Oid::from_raw_bytes(raw_oid))
+        } else {
+            None
+        };
+
+        let mut children = Vec::with_capacity(children_count);
+        for _ in 0..children_count {
+            children.push(Self::deserialize_node(reader)?);
+        }
+
+        Ok(Self {
+            name,
+            entry_count: entry_count.max(0) as usize,
+            oid,
+            children,
+        })
+    }
+}
+
+fn corrupt(reason: &str) -> GitError {
+    GitError::CorruptIndex(format!("cache tree: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn invalidated_nodes_survive_round_trip() {
+        let oid = Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap();
+        let mut cache_tree = CacheTree::new(
+            String::new(),
+            3,
+            oid.clone(),
+            vec![
+                CacheTree::new("a".to_string(), 1, oid.clone(), Vec::new()),
+                CacheTree::new("b".to_string(), 1, oid.clone(), Vec::new()),
+            ],
+        );
+
+        cache_tree.invalidate("b/file");
+
+        let mut serialized = Vec::new();
+        cache_tree.serialize(&mut serialized).unwrap();
+        let deserialized = CacheTree::deserialize(serialized.as_slice()).unwrap();
+
+        assert_eq!(deserialized.oid(), None);
+        assert_eq!(deserialized.find("a").unwrap().oid(), Some(&oid));
+        assert_eq!(deserialized.find("b/").unwrap().oid(), None);
+        assert_eq!(deserialized.find("c"), None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{BufRead, Write};
+
+use crate::{
+    object::{Oid, RawOid},
+    repository::{GitError, GitResult},
+};
+
+/// Entry of the `REUC` extension, which remembers the conflicting stages of a
+/// path once it is resolved, so that the conflict can be recreated
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ResolveUndoEntry {
+    name: String,
+    /// mode and id of stages 1 to 3, if they were present
+    stages: [Option<(u32, Oid)>; 3],
+}
+
+impl ResolveUndoEntry {
+    pub fn new(name: String, stages: [Option<(u32, Oid)>; 3]) -> Self {
+        Self { name, stages }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn stages(&self) -> &[Option<(u32, Oid)>; 3] {
+        &self.stages
+    }
+
+    /// Writes `<name>\0`, then the octal mode of each stage followed by a null
+    /// byte, zero for missing stages, and finally the ids of present stages
+    pub fn serialize<W: Write>(&self, mut writer: W) -> GitResult<()> {
+        writer.write_all(self.name.as_bytes())?;
+        writer.write_all(b"\0")?;
+
+        for stage in &self.stages {
+            let mode = stage.as_ref().map_or(0, |(mode, _)| *mode);
+            write!(writer, "{mode:o}\0")?;
+        }
+
+        for (_, oid) in self.stages.iter().flatten() {
+            writer.write_all(&oid.to_raw_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn deserialize<R: BufRead>(mut reader: R) -> GitResult<Self> {
+        let name = read_until_null(&mut reader)?;
+
+        let mut modes = [0; 3];
+        for mode in &mut modes {
+            *mode = u32::from_str_radix(&read_until_null(&mut reader)?, 8)
+                .map_err(|_| corrupt("invalid mode"))?;
+        }
+
+        let mut stages = [None, None, None];
+        for (stage, mode) in stages.iter_mut().zip(modes) {
+            if mode != 0 {
+                let mut raw_oid = RawOid::default();
+                reader.read_exact(&mut raw_oid)?;
+                *stage = Some((mode, Oid::from_raw_bytes(raw_oid)));
+            }
+        }
+
+        Ok(Self { name, stages })
+    }
+}
+
+fn read_until_null<R: BufRead>(reader: &mut R) -> GitResult<String> {
+    let mut bytes = Vec::new();
+    reader.read_until(b'\0', &mut bytes)?;
+    if bytes.pop() != Some(b'\0') {
+        return Err(corrupt("unterminated field"));
+    }
+
+    String::from_utf8(bytes).map_err(|_| corrupt("field is not utf8"))
+}
+
+fn corrupt(reason: &str) -> GitError {
+    GitError::CorruptIndex(format!("resolve undo: {reason}"))
+}
@@ -0,0 +1,274 @@
+use std::{
+    fs::{self, Metadata},
+    io,
+    os::unix::fs::MetadataExt,
+    path::Path,
+};
+
+use crate::{
+    object::{normalize_mode, Blob},
+    repository::{GitError, GitResult},
+};
+
+use super::{Index, IndexEntry};
+
+/// mode of submodule commits, whose content is another repository
+const GITLINK_MODE: u32 = 0o160000;
+
+/// State of a file compared with its index entry
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryStatus {
+    Unchanged,
+    Modified,
+    Deleted,
+}
+
+/// Why `Index::refresh` could not mark an entry as up to date
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RefreshIssue {
+    /// the file differs from the entry, or is missing
+    NeedsUpdate,
+    /// the entry is conflicted
+    NeedsMerge,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RefreshOptions {
+    /// Do not report missing files
+    pub ignore_missing: bool,
+    /// Also check entries marked as assume unchanged
+    pub really: bool,
+}
+
+impl IndexEntry {
+    /// Whether the cached stat data still describes the file. Any difference
+    /// only means the content has to be compared
+    pub fn matches_stat(&self, metadata: &Metadata) -> bool {
+        self.mtime == metadata.mtime() as i32
+            && self.mtime_nsec == metadata.mtime_nsec() as i32
+            && self.ctime == metadata.ctime() as i32
+            && self.ctime_nsec == metadata.ctime_nsec() as i32
+            && self.dev == metadata.dev() as u32
+            && self.ino == metadata.ino() as u32
+            && self.uid == metadata.uid()
+            && self.gid == metadata.gid()
+            && self.size == metadata.size() as u32
+    }
+
+    /// Whether the file was modified no earlier than the second the index was
+    /// written at. It may then have changed again after its stat data was
+    /// cached, without that data changing
+    pub fn is_racy(&self, timestamp: (i32, i32)) -> bool {
+        self.mode != GITLINK_MODE && self.mtime >= timestamp.0
+    }
+
+    pub fn refresh_stat(&mut self, metadata: &Metadata) {
+        self.ctime = metadata.ctime() as i32;
+        self.ctime_nsec = metadata.ctime_nsec() as i32;
+        self.mtime = metadata.mtime() as i32;
+        self.mtime_nsec = metadata.mtime_nsec() as i32;
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.size() as u32;
+    }
+}
+
+impl Index {
+    /// Modification time of the index file it was read from, as seconds and
+    /// nanoseconds
+    pub fn timestamp(&self) -> Option<(i32, i32)> {
+        self.timestamp
+    }
+
+    fn is_racy(&self, entry: &IndexEntry) -> bool {
+        self.timestamp
+            .is_some_and(|timestamp| entry.is_racy(timestamp))
+    }
+
+    /// Compares an entry with its file in the working tree, only hashing the
+    /// file when its stat data changed or cannot be trusted
+    pub fn entry_status(&self, work_tree: &Path, entry: &IndexEntry) -> GitResult<EntryStatus> {
+        if entry.assume_valid || entry.skip_worktree {
+            return Ok(EntryStatus::Unchanged);
+        }
+
+        let Some(metadata) = file_metadata(work_tree, entry)? else {
+            return Ok(EntryStatus::Deleted);
+        };
+
+        if self.is_modified(work_tree, entry, &metadata)? {
+            Ok(EntryStatus::Modified)
+        } else {
+            Ok(EntryStatus::Unchanged)
+        }
+    }
+
+    fn is_modified(
+        &self,
+        work_tree: &Path,
+        entry: &IndexEntry,
+        metadata: &Metadata,
+    ) -> GitResult<bool> {
+        if entry.mode == GITLINK_MODE {
+            return Ok(!metadata.is_dir());
+        }
+        // a file that cannot be tracked, such as a fifo, replaced the entry
+        match normalize_mode(metadata.mode()) {
+            Ok(mode) if mode == entry.mode => (),
+            Ok(_) | Err(GitError::InvalidFileMode(_)) => return Ok(true),
+            Err(error) => return Err(error),
+        }
+
+        if entry.matches_stat(metadata) && !self.is_racy(entry) {
+            return Ok(false);
+        }
+        // a zero size is never trusted, as entries created without a file
+        // and smudged racy entries have it
+        if entry.size != 0 && entry.size != metadata.size() as u32 {
+            return Ok(true);
+        }
+
+        let path = work_tree.join(&entry.name);
+        let content = if metadata.is_symlink() {
+            fs::read_link(path)?.into_os_string().into_encoded_bytes()
+        } else {
+            fs::read(path)?
+        };
+
+        Ok(Blob::new(content).hash() != entry.oid)
+    }
+
+    /// Caches the stat data of entries whose file is unchanged, so that they
+    /// are not hashed again, returning the entries which are not up to date
+    pub fn refresh(
+        &mut self,
+        work_tree: &Path,
+        options: RefreshOptions,
+    ) -> GitResult<Vec<(String, RefreshIssue)>> {
+        let mut issues: Vec<(String, RefreshIssue)> = Vec::new();
+
+        for position in 0..self.entries.len() {
+            let entry = &self.entries[position];
+
+            if entry.stage != 0 {
+                if issues.last().is_none_or(|(name, _)| *name != entry.name) {
+                    issues.push((entry.name.clone(), RefreshIssue::NeedsMerge));
+                }
+                continue;
+            }
+            if entry.skip_worktree || (entry.assume_valid && !options.really) {
+                continue;
+            }
+
+            let Some(metadata) = file_metadata(work_tree, entry)? else {
+                if !options.ignore_missing {
+                    issues.push((entry.name.clone(), RefreshIssue::NeedsUpdate));
+                }
+                continue;
+            };
+
+            if self.is_modified(work_tree, entry, &metadata)? {
+                issues.push((entry.name.clone(), RefreshIssue::NeedsUpdate));
+            } else {
+                self.entries[position].refresh_stat(&metadata);
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// Clears the size of racy entries whose file changed, before the index
+    /// is written. Once the index is newer than the file, the stat data alone
+    /// would otherwise claim the entry is unchanged
+    pub fn smudge_racy_entries(&mut self, work_tree: &Path) -> GitResult<()> {
+        for position in 0..self.entries.len() {
+            let entry = &self.entries[position];
+            if entry.stage != 0 || entry.size == 0 || !self.is_racy(entry) {
+                continue;
+            }
+
+            let Some(metadata) = file_metadata(work_tree, entry)? else {
+                continue;
+            };
+            if self.is_modified(work_tree, entry, &metadata)? {
+                self.entries[position].size = 0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn file_metadata(work_tree: &Path, entry: &IndexEntry) -> GitResult<Option<Metadata>> {
+    match fs::symlink_metadata(work_tree.join(&entry.name)) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(error)
+            if matches!(
+                error.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn racy_entries_are_rehashed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("file"), b"changed").unwrap();
+
+        // the stat data matches the file, but not the content it was cached for
+        let metadata = fs::symlink_metadata(dir.join("file")).unwrap();
+        let oid = Blob::new(b"content".to_vec()).hash();
+        let entry = IndexEntry::new(metadata, oid, false, 0, "file".to_string()).unwrap();
+        let mut index = Index::new(vec![entry.clone()]);
+
+        index.timestamp = Some((entry.mtime() + 1, 0));
+        assert_eq!(
+            index.entry_status(dir, &entry).unwrap(),
+            EntryStatus::Unchanged
+        );
+
+        index.timestamp = Some((entry.mtime(), 0));
+        assert_eq!(
+            index.entry_status(dir, &entry).unwrap(),
+            EntryStatus::Modified
+        );
+
+        // still detected once the index is written after the file
+        index.smudge_racy_entries(dir).unwrap();
+        index.timestamp = Some((entry.mtime() + 1, 0));
+        let smudged = index.entries()[0].clone();
+        assert_eq!(
+            index.entry_status(dir, &smudged).unwrap(),
+            EntryStatus::Modified
+        );
+
+        fs::remove_file(dir.join("file")).unwrap();
+        assert_eq!(
+            index.refresh(dir, RefreshOptions::default()).unwrap(),
+            vec![("file".to_string(), RefreshIssue::NeedsUpdate)]
+        );
+
+        // a fifo cannot be tracked, but only makes the entry modified
+        let path =
+            std::ffi::CString::new(dir.join("file").into_os_string().into_encoded_bytes()).unwrap();
+        // SAFETY: the path is a valid nul terminated string
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o644) }, 0);
+        assert_eq!(
+            index.entry_status(dir, &entry).unwrap(),
+            EntryStatus::Modified
+        );
+    }
+}
@@ -1,7 +1,10 @@
 pub mod command;
+pub mod config;
+pub mod fsck;
 
 pub mod index;
 pub mod object;
+pub mod pack;
 pub mod repository;
 
 mod utils;
@@ -1,7 +1,15 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, process::ExitCode};
 
-use clap::{Parser, Subcommand};
-use grit::{command, repository::GitResult};
+use clap::{CommandFactory, Parser, Subcommand};
+use grit::{
+    command::{
+        self, CatFileMode, ConfigAction, ConfigLocation, ConfigOptions, ConfigType, LsFilesOptions,
+        LsTreeOptions, SymbolicRefAction, UpdateIndexOptions,
+    },
+    config::ConfigScope,
+    pack::DeltaOptions,
+    repository::{GitResult, InitOptions, RepackOptions},
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -12,17 +20,152 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Command {
-    Init,
+    /// Create a repository, or complete an existing one
+    Init {
+        /// Create a repository without a working tree
+        #[arg(long)]
+        bare: bool,
+        /// Name of the branch HEAD points to
+        #[arg(short = 'b', long, value_name = "BRANCH")]
+        initial_branch: Option<String>,
+        /// Directory whose files are copied into the git dir
+        #[arg(long, value_name = "DIRECTORY")]
+        template: Option<PathBuf>,
+        /// Store the git dir there, leaving a `.grit` file pointing to it
+        #[arg(long, value_name = "GIT_DIR", conflicts_with = "bare")]
+        separate_git_dir: Option<PathBuf>,
+        /// Directory of the repository, created if needed
+        directory: Option<PathBuf>,
+    },
     HashObject {
         path: PathBuf,
     },
     CatFile {
-        hash: String,
+        /// Show the object type
+        #[arg(short = 't', group = "mode")]
+        show_type: bool,
+        /// Show the object size
+        #[arg(short = 's', group = "mode")]
+        show_size: bool,
+        /// Pretty-print the object content
+        #[arg(short = 'p', group = "mode")]
+        pretty: bool,
+        /// Exit with zero status if the object exists
+        #[arg(short = 'e', group = "mode")]
+        exists: bool,
+        /// Print type, size and content of each object read from stdin
+        #[arg(long, group = "mode", conflicts_with = "args")]
+        batch: bool,
+        /// Print type and size of each object read from stdin
+        #[arg(long, group = "mode", conflicts_with = "args")]
+        batch_check: bool,
+        /// Either `<object>`, or `<type> <object>` when no mode is given
+        #[arg(num_args = 0..=2)]
+        args: Vec<String>,
     },
     UpdateIndex {
-        path: PathBuf,
+        /// Add files which are not in the index yet
+        #[arg(long)]
+        add: bool,
+        /// Remove entries whose file is missing
+        #[arg(long)]
+        remove: bool,
+        /// Remove entries even if their file still exists
+        #[arg(long)]
+        force_remove: bool,
+        /// Add an entry without reading a file
+        #[arg(long, value_name = "MODE>,<OID>,<PATH")]
+        cacheinfo: Vec<String>,
+        /// Set or clear the executable bit of the paths
+        #[arg(long, value_parser = ["+x", "-x"])]
+        chmod: Option<String>,
+        /// Mark the paths as unchanged, so that their files are not checked
+        #[arg(long, conflicts_with = "no_assume_unchanged")]
+        assume_unchanged: bool,
+        #[arg(long)]
+        no_assume_unchanged: bool,
+        /// Mark the paths as absent from the working tree
+        #[arg(long, conflicts_with = "no_skip_worktree")]
+        skip_worktree: bool,
+        #[arg(long)]
+        no_skip_worktree: bool,
+        /// Read entries from stdin
+        #[arg(long)]
+        index_info: bool,
+        /// Restore the conflicting stages of the resolved paths
+        #[arg(long)]
+        unresolve: bool,
+        #[arg(long)]
+        index_version: Option<u32>,
+        /// Lines read from stdin are terminated by a null byte
+        #[arg(short)]
+        zero_terminated: bool,
+        /// Check whether the files of entries changed, caching their stat data
+        #[arg(long)]
+        refresh: bool,
+        /// Like --refresh, but also check entries marked as assume unchanged
+        #[arg(long)]
+        really_refresh: bool,
+        /// Continue when entries need an update, without reporting them
+        #[arg(short)]
+        quiet: bool,
+        /// Ignore missing files when refreshing
+        #[arg(long)]
+        ignore_missing: bool,
+        paths: Vec<PathBuf>,
+    },
+    LsFiles {
+        /// Show cached files, the default
+        #[arg(short, long)]
+        cached: bool,
+        /// Show mode, object id and stage of each entry
+        #[arg(short, long)]
+        stage: bool,
+        /// Show deleted files
+        #[arg(short, long)]
+        deleted: bool,
+        /// Show modified files
+        #[arg(short, long)]
+        modified: bool,
+        /// Show untracked files
+        #[arg(short, long)]
+        others: bool,
+        /// Show unmerged entries
+        #[arg(short, long)]
+        unmerged: bool,
+        /// Show cached stat data of each entry
+        #[arg(long)]
+        debug: bool,
+        /// Terminate entries with a null byte
+        #[arg(short = 'z')]
+        zero_terminated: bool,
+    },
+    WriteTree {
+        /// Write the tree of this subdirectory instead of the root
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    LsTree {
+        /// Recurse into subtrees
+        #[arg(short = 'r')]
+        recursive: bool,
+        /// Show trees when recursing
+        #[arg(short = 't')]
+        show_trees: bool,
+        /// Show only trees
+        #[arg(short = 'd')]
+        only_trees: bool,
+        /// Show only paths
+        #[arg(long, alias = "name-status")]
+        name_only: bool,
+        /// Show the size of blobs
+        #[arg(short = 'l', long)]
+        long: bool,
+        /// Commit, tag or tree whose contents are listed
+        tree_ish: String,
+        /// Only show entries matching these paths
+        paths: Vec<String>,
     },
-    WriteTree,
     CommitTree {
         hash: String,
         #[arg(short, long)]
@@ -33,24 +176,538 @@ enum Command {
     UpdateRef {
         hash: String,
     },
+    /// Read, change or delete a symbolic reference such as `HEAD`
+    SymbolicRef {
+        /// Print the name without its directory, e.g. `master`
+        #[arg(long, conflicts_with_all = ["target", "delete"])]
+        short: bool,
+        /// Print the target as stored, without following it further
+        #[arg(long, conflicts_with_all = ["target", "delete"])]
+        no_recurse: bool,
+        /// Delete the symbolic reference
+        #[arg(short, long, conflicts_with = "target")]
+        delete: bool,
+        /// Exit with status 1 instead of failing if the reference is not symbolic
+        #[arg(short, long)]
+        quiet: bool,
+        name: String,
+        /// Reference to point to, under `refs/`
+        target: Option<String>,
+    },
+    /// Create a tag object from stdin
+    Mktag,
+    Tag {
+        /// Name of the tag to create or delete
+        name: Option<String>,
+        /// Object the new tag points to, defaults to HEAD
+        object: Option<String>,
+        /// Create an annotated tag
+        #[arg(short, long, requires = "message")]
+        annotate: bool,
+        /// Message of the annotated tag
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Replace an existing tag
+        #[arg(short, long)]
+        force: bool,
+        /// List tags matching the given patterns
+        #[arg(short, long, num_args = 0.., value_name = "PATTERN", conflicts_with_all = ["name", "delete"])]
+        list: Option<Vec<String>>,
+        /// Delete the tag
+        #[arg(short, long, requires = "name")]
+        delete: bool,
+    },
+    /// Create a pack of the objects listed on stdin
+    PackObjects {
+        /// Write `<base-name>-<checksum>.pack` and its index
+        #[arg(required_unless_present = "stdout", conflicts_with = "stdout")]
+        base_name: Option<PathBuf>,
+        /// Write the pack to stdout instead
+        #[arg(long)]
+        stdout: bool,
+        /// Read revisions instead of object ids, packing everything reachable
+        /// from them except from those prefixed with `^`
+        #[arg(long)]
+        revs: bool,
+        /// Number of objects each one is compared against to find a delta base
+        #[arg(long, default_value_t = DeltaOptions::default().window)]
+        window: usize,
+        /// Maximum length of delta chains
+        #[arg(long, default_value_t = DeltaOptions::default().depth)]
+        depth: usize,
+    },
+    /// Pack loose objects, or every reachable object with `-a`
+    Repack {
+        /// Pack every reachable object into a single pack
+        #[arg(short = 'a')]
+        all: bool,
+        /// Like `-a`, but keep unreachable objects of removed packs as loose objects
+        #[arg(short = 'A')]
+        all_keep_unreachable: bool,
+        /// Remove redundant packs and loose objects
+        #[arg(short = 'd')]
+        delete: bool,
+        /// Number of objects each one is compared against to find a delta base
+        #[arg(long, default_value_t = DeltaOptions::default().window)]
+        window: usize,
+        /// Maximum length of delta chains
+        #[arg(long, default_value_t = DeltaOptions::default().depth)]
+        depth: usize,
+    },
+    /// Remove loose objects that are already in a pack
+    PrunePacked {
+        /// Only print the files that would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Remove unreachable loose objects
+    Prune {
+        /// Only print the objects that would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Only remove objects older than this date, e.g. `2.weeks.ago`
+        #[arg(long)]
+        expire: Option<String>,
+    },
+    /// Move references into `packed-refs`
+    PackRefs {
+        /// Pack every reference, not only tags
+        #[arg(long)]
+        all: bool,
+        /// Keep loose references after packing them
+        #[arg(long)]
+        no_prune: bool,
+    },
+    /// Verify the objects and connectivity of the repository
+    Fsck {
+        /// Show every unreachable object, not only dangling ones
+        #[arg(long)]
+        unreachable: bool,
+        /// Do not show dangling objects
+        #[arg(long)]
+        no_dangling: bool,
+    },
+    /// Clean up and optimize the repository
+    Gc {
+        /// Only run if there are too many loose objects or packs
+        #[arg(long)]
+        auto: bool,
+        /// Prune unreachable objects older than this date, `2.weeks.ago` by default
+        #[arg(long, value_name = "DATE", num_args = 0..=1, default_missing_value = "now")]
+        prune: Option<String>,
+        /// Do not prune unreachable objects
+        #[arg(long, conflicts_with = "prune")]
+        no_prune: bool,
+    },
+    /// Get and set options of the repository or the user
+    Config {
+        /// Use the system configuration
+        #[arg(long, group = "location")]
+        system: bool,
+        /// Use the configuration of the user
+        #[arg(long, group = "location")]
+        global: bool,
+        /// Use the configuration of the repository, the default when writing
+        #[arg(long, group = "location")]
+        local: bool,
+        /// Use the configuration of the working tree
+        #[arg(long, group = "location")]
+        worktree: bool,
+        /// Use the given file
+        #[arg(short, long, group = "location")]
+        file: Option<PathBuf>,
+        /// Get the value which takes precedence, the default with a single argument
+        #[arg(long, group = "action")]
+        get: bool,
+        /// Get every value
+        #[arg(long, group = "action")]
+        get_all: bool,
+        /// Get the values of every key matching a regular expression
+        #[arg(long, group = "action")]
+        get_regexp: bool,
+        /// Replace every value matching the pattern with a single one
+        #[arg(long, group = "action")]
+        replace_all: bool,
+        /// Add a value without changing the existing ones
+        #[arg(long, group = "action")]
+        add: bool,
+        /// Remove the value
+        #[arg(long, group = "action")]
+        unset: bool,
+        /// Remove every value
+        #[arg(long, group = "action")]
+        unset_all: bool,
+        /// List every value
+        #[arg(short, long, group = "action")]
+        list: bool,
+        /// Check and normalize values as this type
+        #[arg(long = "type", value_parser = ["bool", "int", "bool-or-int", "path"])]
+        value_type: Option<String>,
+        /// Same as --type=bool
+        #[arg(long, conflicts_with_all = ["value_type", "int"])]
+        bool: bool,
+        /// Same as --type=int
+        #[arg(long, conflicts_with = "value_type")]
+        int: bool,
+        /// Terminate values with a null byte
+        #[arg(short = 'z', long = "null")]
+        zero_terminated: bool,
+        /// `<key> [<value> [<value-pattern>]]`, or `<name-regex> [<value-pattern>]`
+        #[arg(num_args = 0..=3)]
+        args: Vec<String>,
+    },
 }
 
-fn main() -> GitResult<()> {
+fn main() -> GitResult<ExitCode> {
     let cli = Cli::parse();
 
     match cli.command {
-        Command::Init => command::init()?,
+        Command::Init {
+            bare,
+            initial_branch,
+            template,
+            separate_git_dir,
+            directory,
+        } => command::init(
+            directory,
+            bare,
+            separate_git_dir,
+            InitOptions {
+                initial_branch,
+                template_dir: template,
+            },
+        )?,
         Command::HashObject { path } => command::hash_object(path)?,
-        Command::CatFile { hash } => command::cat_file(hash)?,
-        Command::UpdateIndex { path } => command::update_index(path)?,
-        Command::WriteTree => command::write_tree()?,
+        Command::CatFile {
+            show_type,
+            show_size,
+            pretty,
+            exists,
+            batch,
+            batch_check,
+            mut args,
+        } => {
+            if batch || batch_check {
+                command::cat_file_batch(batch)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            let mode = if show_type {
+                Some(CatFileMode::Type)
+            } else if show_size {
+                Some(CatFileMode::Size)
+            } else if pretty {
+                Some(CatFileMode::Pretty)
+            } else {
+                None
+            };
+
+            let (mode, hash) = match (mode, args.len()) {
+                (Some(mode), 1) => (Some(mode), args.remove(0)),
+                (None, 1) if exists => (None, args.remove(0)),
+                // for backwards compatibility, a lone object is pretty-printed
+                (None, 1) => (Some(CatFileMode::Pretty), args.remove(0)),
+                (None, 2) if !exists => {
+                    let kind = args.remove(0).parse()?;
+                    (Some(CatFileMode::Kind(kind)), args.remove(0))
+                }
+                _ => {
+                    Cli::command()
+                        .error(
+                            clap::error::ErrorKind::WrongNumberOfValues,
+                            "expected `<object>` with a mode, or `<type> <object>`",
+                        )
+                        .exit();
+                }
+            };
+
+            match mode {
+                Some(mode) => command::cat_file(hash, mode)?,
+                None => {
+                    if !command::cat_file_exists(hash)? {
+                        return Ok(ExitCode::FAILURE);
+                    }
+                }
+            }
+        }
+        Command::UpdateIndex {
+            add,
+            remove,
+            force_remove,
+            cacheinfo,
+            chmod,
+            assume_unchanged,
+            no_assume_unchanged,
+            skip_worktree,
+            no_skip_worktree,
+            index_info,
+            unresolve,
+            index_version,
+            zero_terminated,
+            refresh,
+            really_refresh,
+            quiet,
+            ignore_missing,
+            paths,
+        } => {
+            let options = UpdateIndexOptions {
+                add,
+                remove,
+                force_remove,
+                cacheinfo,
+                chmod: chmod.map(|chmod| chmod == "+x"),
+                assume_unchanged: flag(assume_unchanged, no_assume_unchanged),
+                skip_worktree: flag(skip_worktree, no_skip_worktree),
+                index_info,
+                unresolve,
+                index_version,
+                zero_terminated,
+                refresh,
+                really_refresh,
+                quiet,
+                ignore_missing,
+            };
+            if !command::update_index(paths, options)? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::LsFiles {
+            cached,
+            stage,
+            deleted,
+            modified,
+            others,
+            unmerged,
+            debug,
+            zero_terminated,
+        } => command::ls_files(LsFilesOptions {
+            cached,
+            stage,
+            deleted,
+            modified,
+            others,
+            unmerged,
+            debug,
+            zero_terminated,
+        })?,
+        Command::WriteTree { prefix } => command::write_tree(prefix)?,
+        Command::LsTree {
+            recursive,
+            show_trees,
+            only_trees,
+            name_only,
+            long,
+            tree_ish,
+            paths,
+        } => command::ls_tree(
+            tree_ish,
+            paths,
+            LsTreeOptions {
+                recursive,
+                show_trees,
+                only_trees,
+                name_only,
+                long,
+            },
+        )?,
         Command::CommitTree {
             hash,
             message,
             parent,
         } => command::commit_tree(hash, parent, message)?,
         Command::UpdateRef { hash } => command::update_ref(hash)?,
+        Command::SymbolicRef {
+            short,
+            no_recurse,
+            delete,
+            quiet,
+            name,
+            target,
+        } => {
+            let action = match target {
+                Some(target) => SymbolicRefAction::Set { target },
+                None if delete => SymbolicRefAction::Delete,
+                None => SymbolicRefAction::Read {
+                    short,
+                    recurse: !no_recurse,
+                },
+            };
+            if !command::symbolic_ref(name, action, quiet)? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Mktag => command::mktag()?,
+        Command::Tag {
+            name,
+            object,
+            annotate: _,
+            message,
+            force,
+            list,
+            delete,
+        } => match name {
+            Some(name) if delete => command::tag_delete(name)?,
+            Some(name) => command::tag_create(name, object, message, force)?,
+            None => command::tag_list(list.unwrap_or_default())?,
+        },
+        Command::PackObjects {
+            base_name,
+            stdout: _,
+            revs,
+            window,
+            depth,
+        } => command::pack_objects(base_name, revs, DeltaOptions { window, depth })?,
+        Command::Repack {
+            all,
+            all_keep_unreachable,
+            delete,
+            window,
+            depth,
+        } => command::repack(RepackOptions {
+            all: all || all_keep_unreachable,
+            delete,
+            keep_unreachable: all_keep_unreachable,
+            unreachable_expire: None,
+            delta: DeltaOptions { window, depth },
+        })?,
+        Command::PrunePacked { dry_run } => command::prune_packed(dry_run)?,
+        Command::Prune { dry_run, expire } => command::prune(expire, dry_run)?,
+        Command::PackRefs { all, no_prune } => command::pack_refs(all, !no_prune)?,
+        Command::Fsck {
+            unreachable,
+            no_dangling,
+        } => {
+            if !command::fsck(unreachable, !no_dangling)? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Gc {
+            auto,
+            prune,
+            no_prune,
+        } => {
+            let prune = if no_prune {
+                Some("never".to_string())
+            } else {
+                prune
+            };
+            command::gc(auto, prune)?
+        }
+        Command::Config {
+            system,
+            global,
+            local,
+            worktree,
+            file,
+            get,
+            get_all,
+            get_regexp,
+            replace_all,
+            add,
+            unset,
+            unset_all,
+            list,
+            value_type,
+            bool,
+            int,
+            zero_terminated,
+            args,
+        } => {
+            let location = if system {
+                Some(ConfigLocation::Scope(ConfigScope::System))
+            } else if global {
+                Some(ConfigLocation::Scope(ConfigScope::Global))
+            } else if local {
+                Some(ConfigLocation::Scope(ConfigScope::Local))
+            } else if worktree {
+                Some(ConfigLocation::Scope(ConfigScope::Worktree))
+            } else {
+                file.map(ConfigLocation::File)
+            };
+
+            let value_type = match value_type.as_deref() {
+                Some("bool") => Some(ConfigType::Bool),
+                Some("int") => Some(ConfigType::Int),
+                Some("bool-or-int") => Some(ConfigType::BoolOrInt),
+                Some("path") => Some(ConfigType::Path),
+                _ if bool => Some(ConfigType::Bool),
+                _ if int => Some(ConfigType::Int),
+                _ => None,
+            };
+
+            let mut args = args.into_iter();
+            let (first, second, third) = (args.next(), args.next(), args.next());
+            let action = match (first, second, third) {
+                (None, None, None) if list => ConfigAction::List,
+                (Some(name_pattern), value_pattern, None) if get_regexp => {
+                    ConfigAction::GetRegexp {
+                        name_pattern,
+                        value_pattern,
+                    }
+                }
+                (Some(key), value_pattern, None) if get_all => {
+                    ConfigAction::GetAll { key, value_pattern }
+                }
+                (Some(key), value_pattern, None) if unset => {
+                    ConfigAction::Unset { key, value_pattern }
+                }
+                (Some(key), value_pattern, None) if unset_all => {
+                    ConfigAction::UnsetAll { key, value_pattern }
+                }
+                (Some(key), Some(value), None) if add => ConfigAction::Add { key, value },
+                (Some(key), Some(value), value_pattern) if replace_all => {
+                    ConfigAction::ReplaceAll {
+                        key,
+                        value,
+                        value_pattern,
+                    }
+                }
+                (Some(key), value_pattern, None) if get => ConfigAction::Get { key, value_pattern },
+                (Some(key), None, None) if !(list || add || replace_all) => ConfigAction::Get {
+                    key,
+                    value_pattern: None,
+                },
+                (Some(key), Some(value), value_pattern)
+                    if !(list || get || get_all || get_regexp || add || replace_all)
+                        && !(unset || unset_all) =>
+                {
+                    ConfigAction::Set {
+                        key,
+                        value,
+                        value_pattern,
+                    }
+                }
+                _ => {
+                    Cli::command()
+                        .error(
+                            clap::error::ErrorKind::WrongNumberOfValues,
+                            "wrong number of arguments for the action",
+                        )
+                        .exit();
+                }
+            };
+
+            let options = ConfigOptions {
+                location,
+                value_type,
+                zero_terminated,
+            };
+            if !command::config(action, options)? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Value of a flag given as `--<flag>` or `--no-<flag>`, if either was
+fn flag(set: bool, unset: bool) -> Option<bool> {
+    if set {
+        Some(true)
+    } else if unset {
+        Some(false)
+    } else {
+        None
+    }
 }
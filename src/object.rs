@@ -1,14 +1,28 @@
-use std::{fmt::Display, ops::Deref};
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader, Read, Write},
+    ops::Deref,
+    str::FromStr,
+};
+
+use flate2::{
+    read::ZlibDecoder as ZlibReadDecoder, write::ZlibEncoder as ZlibWriteEncoder, Compression,
+};
+use sha1::{Digest, Sha1};
 
 use crate::repository::{GitError, GitResult};
 
 mod blob;
 mod commit;
+mod signature;
+mod tag;
 mod tree;
 
 pub use blob::Blob;
 pub use commit::Commit;
-pub use tree::{Tree, TreeEntry};
+pub use signature::{parse_date, parse_expiry_date, Signature};
+pub use tag::Tag;
+pub use tree::{normalize_mode, Tree, TreeEntry};
 
 #[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Oid(String);
@@ -17,17 +31,31 @@ pub const OID_HEX_LEN: usize = 20;
 pub type RawOid = [u8; OID_HEX_LEN];
 
 impl Oid {
+    /// Parses a hexadecimal object id in either case, stored lowercase
     pub fn new(id: impl Into<String>) -> GitResult<Self> {
-        let hex_id = id.into();
+        let mut hex_id = id.into();
         let decoded_len =
             base16ct::decoded_len(hex_id.as_bytes()).map_err(|_| GitError::InvalidOid)?;
-        if decoded_len != OID_HEX_LEN {
+        if decoded_len != OID_HEX_LEN || !hex_id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
             Err(GitError::InvalidOid)
         } else {
+            hex_id.make_ascii_lowercase();
             Ok(Self(hex_id))
         }
     }
 
+    /// Parses an object id stored in an object, which git always writes in
+    /// lowercase: accepting other cases would change the object once rewritten
+    pub(crate) fn from_object_header(value: Vec<u8>) -> GitResult<Self> {
+        if !value
+            .iter()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+        {
+            return Err(GitError::InvalidOid);
+        }
+        Self::new(String::from_utf8(value).map_err(|_| GitError::InvalidOid)?)
+    }
+
     pub fn to_raw_bytes(&self) -> RawOid {
         let raw_id = base16ct::lower::decode_vec(self.0.as_bytes()).expect("should never fail");
         raw_id.try_into().expect("should never fail")
@@ -58,3 +86,330 @@ impl Display for Oid {
         self.0.fmt(f)
     }
 }
+
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum ObjectKind {
+    Blob,
+    Tree,
+    Commit,
+    Tag,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tag => "tag",
+        }
+    }
+}
+
+impl FromStr for ObjectKind {
+    type Err = GitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(ObjectKind::Blob),
+            "tree" => Ok(ObjectKind::Tree),
+            "commit" => Ok(ObjectKind::Commit),
+            "tag" => Ok(ObjectKind::Tag),
+            _ => Err(GitError::InvalidObjectKind(s.to_string())),
+        }
+    }
+}
+
+impl Display for ObjectKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// Builds the header that prefixes every object, both when hashing and when storing it
+pub fn object_header(kind: ObjectKind, size: usize) -> Vec<u8> {
+    format!("{kind} {size}\0").into_bytes()
+}
+
+/// Splits the header lines of a commit or tag into key value pairs, joining
+/// continuation lines with a line feed. Values are kept as bytes, since they
+/// are in the encoding of the object rather than necessarily in utf8
+pub(crate) fn parse_headers(headers: &[u8], kind: ObjectKind) -> GitResult<Vec<(String, Vec<u8>)>> {
+    let malformed = |reason: &str| GitError::MalformedObject {
+        kind,
+        reason: reason.to_string(),
+    };
+
+    let mut parsed: Vec<(String, Vec<u8>)> = Vec::new();
+
+    for line in headers.split(|&byte| byte == b'\n') {
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = parsed
+                .last_mut()
+                .ok_or_else(|| malformed("continuation line without header"))?;
+            value.push(b'\n');
+            value.extend_from_slice(continuation);
+        } else {
+            let (key, value) =
+                split_once(line, b" ").ok_or_else(|| malformed("header without value"))?;
+            let key = std::str::from_utf8(key).map_err(|_| malformed("header is not utf8"))?;
+            parsed.push((key.to_string(), value.to_vec()));
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Splits bytes around the first occurrence of the delimiter
+pub(crate) fn split_once<'a>(bytes: &'a [u8], delimiter: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    let position = bytes
+        .windows(delimiter.len())
+        .position(|window| window == delimiter)?;
+
+    Some((&bytes[..position], &bytes[position + delimiter.len()..]))
+}
+
+/// An object whose header has been validated, but whose content has not been parsed
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RawObject {
+    kind: ObjectKind,
+    content: Vec<u8>,
+}
+
+impl RawObject {
+    pub fn new(kind: ObjectKind, content: Vec<u8>) -> Self {
+        Self { kind, content }
+    }
+
+    pub fn kind(&self) -> ObjectKind {
+        self.kind
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    pub fn into_content(self) -> Vec<u8> {
+        self.content
+    }
+
+    pub fn size(&self) -> usize {
+        self.content.len()
+    }
+
+    pub fn header(&self) -> Vec<u8> {
+        object_header(self.kind, self.size())
+    }
+
+    pub fn hash(&self) -> Oid {
+        let mut hasher = Sha1::new();
+
+        hasher.update(self.header());
+        hasher.update(&self.content);
+
+        let raw_id = hasher.finalize().into();
+
+        Oid::from_raw_bytes(raw_id)
+    }
+
+    pub fn serialize<W: Write>(&self, writer: W) -> GitResult<()> {
+        let mut encoder = ZlibWriteEncoder::new(writer, Compression::default());
+
+        encoder.write_all(&self.header())?;
+        encoder.write_all(&self.content)?;
+        encoder.finish()?;
+
+        Ok(())
+    }
+
+    /// Inflates a loose object, verifying that the size declared in its header
+    /// matches the actual length of the content
+    pub fn deserialize<R: Read>(reader: R) -> GitResult<Self> {
+        let mut decoder = BufReader::new(ZlibReadDecoder::new(reader));
+
+        let (kind, size) = Self::deserialize_header(&mut decoder)?;
+
+        // the header is not trusted for allocation, and one byte past it is
+        // enough to tell that the content is longer
+        let mut content = Vec::new();
+        decoder
+            .take((size as u64).saturating_add(1))
+            .read_to_end(&mut content)?;
+
+        if content.len() != size {
+            return Err(GitError::ObjectSizeMismatch {
+                expected: size,
+                found: content.len(),
+            });
+        }
+
+        Ok(Self { kind, content })
+    }
+
+    /// Reads the `<kind> <size>\0` header from an already inflated stream
+    pub fn deserialize_header<R: BufRead>(mut reader: R) -> GitResult<(ObjectKind, usize)> {
+        let mut header_bytes = Vec::new();
+        reader.read_until(b'\0', &mut header_bytes)?;
+        if header_bytes.pop() != Some(b'\0') {
+            return Err(GitError::InvalidObjectHeader);
+        }
+
+        let header = String::from_utf8(header_bytes).map_err(|_| GitError::InvalidObjectHeader)?;
+        let (kind, size) = header
+            .split_once(' ')
+            .ok_or(GitError::InvalidObjectHeader)?;
+
+        let kind = kind.parse()?;
+        let size = size.parse().map_err(|_| GitError::InvalidObjectHeader)?;
+
+        Ok((kind, size))
+    }
+
+    /// Inflates only the header of a loose object, without reading its content
+    pub fn deserialize_kind_and_size<R: Read>(reader: R) -> GitResult<(ObjectKind, usize)> {
+        let decoder = BufReader::new(ZlibReadDecoder::new(reader));
+        Self::deserialize_header(decoder)
+    }
+}
+
+/// A fully parsed object
+#[derive(PartialEq, Eq, Debug)]
+pub enum Object {
+    Blob(Blob),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl Object {
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            Object::Blob(_) => ObjectKind::Blob,
+            Object::Tree(_) => ObjectKind::Tree,
+            Object::Commit(_) => ObjectKind::Commit,
+            Object::Tag(_) => ObjectKind::Tag,
+        }
+    }
+
+    pub fn from_raw(raw: RawObject) -> GitResult<Self> {
+        match raw.kind {
+            ObjectKind::Blob => Ok(Object::Blob(Blob::new(raw.content))),
+            ObjectKind::Tree => Ok(Object::Tree(Tree::deserialize_content(&raw.content)?)),
+            ObjectKind::Commit => Ok(Object::Commit(Commit::deserialize_content(&raw.content)?)),
+            ObjectKind::Tag => Ok(Object::Tag(Tag::deserialize_content(&raw.content)?)),
+        }
+    }
+
+    pub fn to_raw(&self) -> RawObject {
+        let mut content = Vec::new();
+        match self {
+            Object::Blob(blob) => content.extend_from_slice(blob.content()),
+            Object::Tree(tree) => tree
+                .serialize_content(&mut content)
+                .expect("writing to vector cannot fail"),
+            Object::Commit(commit) => commit
+                .serialize_content(&mut content)
+                .expect("writing to vector cannot fail"),
+            Object::Tag(tag) => tag
+                .serialize_content(&mut content)
+                .expect("writing to vector cannot fail"),
+        };
+        RawObject::new(self.kind(), content)
+    }
+
+    pub fn hash(&self) -> Oid {
+        match self {
+            Object::Blob(blob) => blob.hash(),
+            Object::Tree(tree) => tree.hash(),
+            Object::Commit(commit) => commit.hash(),
+            Object::Tag(tag) => tag.hash(),
+        }
+    }
+
+    pub fn serialize<W: Write>(&self, writer: W) -> GitResult<()> {
+        match self {
+            Object::Blob(blob) => blob.serialize(writer),
+            Object::Tree(tree) => tree.serialize(writer),
+            Object::Commit(commit) => commit.serialize(writer),
+            Object::Tag(tag) => tag.serialize(writer),
+        }
+    }
+
+    pub fn deserialize<R: Read>(reader: R) -> GitResult<Self> {
+        Self::from_raw(RawObject::deserialize(reader)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn rejects_size_mismatch() {
+        let mut serialized = Vec::new();
+        let mut encoder = ZlibWriteEncoder::new(&mut serialized, Compression::default());
+        encoder.write_all(b"blob 10\0hello").unwrap();
+        encoder.finish().unwrap();
+
+        let result = RawObject::deserialize(serialized.as_slice());
+
+        assert!(matches!(
+            result,
+            Err(GitError::ObjectSizeMismatch {
+                expected: 10,
+                found: 5
+            })
+        ));
+    }
+
+    #[test]
+    pub fn rejects_sizes_beyond_the_content() {
+        let mut serialized = Vec::new();
+        let mut encoder = ZlibWriteEncoder::new(&mut serialized, Compression::default());
+        encoder.write_all(b"blob 99999999999999999\0hello").unwrap();
+        encoder.finish().unwrap();
+
+        let result = RawObject::deserialize(serialized.as_slice());
+
+        assert!(matches!(
+            result,
+            Err(GitError::ObjectSizeMismatch {
+                expected: 99999999999999999,
+                found: 5
+            })
+        ));
+    }
+
+    #[test]
+    pub fn dispatches_by_header_kind() {
+        let blob = Blob::new(b"hello\n".to_vec());
+
+        let mut serialized = Vec::new();
+        blob.serialize(&mut serialized).unwrap();
+
+        let object = Object::deserialize(serialized.as_slice()).unwrap();
+
+        assert_eq!(object.kind(), ObjectKind::Blob);
+        assert_eq!(
+            object.hash().as_ref(),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+    }
+
+    #[test]
+    pub fn lowercases_object_ids() {
+        let oid = Oid::new("CE013625030BA8DBA906F756967F9E9CA394464A").unwrap();
+
+        assert_eq!(oid.as_ref(), "ce013625030ba8dba906f756967f9e9ca394464a");
+        assert!(Oid::new("ce013625030ba8dba906f756967f9e9ca394464g").is_err());
+        assert!(
+            Oid::from_object_header(b"CE013625030BA8DBA906F756967F9E9CA394464A".to_vec()).is_err()
+        );
+        assert_eq!(
+            Oid::from_object_header(b"ce013625030ba8dba906f756967f9e9ca394464a".to_vec()).unwrap(),
+            oid
+        );
+    }
+}
@@ -1,53 +1,65 @@
-use std::io::{self, BufRead, BufReader, Read, Write};
+use std::io::{Read, Write};
 
-use flate2::{
-    read::ZlibDecoder as ZlibReadDecoder, write::ZlibEncoder as ZlibWriteEncoder, Compression,
-};
+use flate2::{write::ZlibEncoder as ZlibWriteEncoder, Compression};
 use sha1::{Digest, Sha1};
 
-use crate::repository::GitResult;
+use crate::repository::{GitError, GitResult};
 
-use super::Oid;
+use super::{object_header, ObjectKind, Oid, RawObject};
 
-pub struct Blob;
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct Blob {
+    content: Vec<u8>,
+}
 
 impl Blob {
-    pub fn hash<R: Read>(mut plain: R, length: usize) -> GitResult<Oid> {
-        let mut hasher = Sha1::new();
+    pub fn new(content: Vec<u8>) -> Self {
+        Self { content }
+    }
 
-        let header = Self::header(length);
-        hasher.update(&header);
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
 
-        io::copy(&mut plain, &mut hasher)?;
+    pub fn into_content(self) -> Vec<u8> {
+        self.content
+    }
+
+    pub fn hash(&self) -> Oid {
+        let mut hasher = Sha1::new();
+
+        hasher.update(self.header());
+        hasher.update(&self.content);
 
         let raw_id = hasher.finalize().into();
-        let id = Oid::from_raw_bytes(raw_id);
 
-        Ok(id)
+        Oid::from_raw_bytes(raw_id)
     }
 
-    pub fn serialize<R: Read, W: Write>(mut src: R, dst: W, length: usize) -> GitResult<()> {
-        let mut encoder = ZlibWriteEncoder::new(dst, Compression::default());
+    pub fn serialize<W: Write>(&self, writer: W) -> GitResult<()> {
+        let mut encoder = ZlibWriteEncoder::new(writer, Compression::default());
 
-        encoder.write_all(&Self::header(length))?;
-        io::copy(&mut src, &mut encoder)?;
+        encoder.write_all(&self.header())?;
+        encoder.write_all(&self.content)?;
+        encoder.finish()?;
 
         Ok(())
     }
 
-    pub fn deserialize<R: Read, W: Write>(src: R, mut dst: W) -> GitResult<()> {
-        let mut decoder = BufReader::new(ZlibReadDecoder::new(src));
-
-        let mut object_header = Vec::new();
-        decoder.read_until(0, &mut object_header)?;
-
-        io::copy(&mut decoder, &mut dst)?;
+    pub fn deserialize<R: Read>(reader: R) -> GitResult<Self> {
+        let raw = RawObject::deserialize(reader)?;
+        if raw.kind() != ObjectKind::Blob {
+            return Err(GitError::UnexpectedObjectKind {
+                expected: ObjectKind::Blob,
+                found: raw.kind(),
+            });
+        }
 
-        Ok(())
+        Ok(Self::new(raw.into_content()))
     }
 
-    pub fn header(size: usize) -> Vec<u8> {
-        format!("blob {size}\0").bytes().collect()
+    pub fn header(&self) -> Vec<u8> {
+        object_header(ObjectKind::Blob, self.content.len())
     }
 }
 
@@ -65,13 +77,14 @@ mod tests {
         for _ in 0..100 {
             rng_core.fill_bytes(&mut original);
 
+            let blob = Blob::new(original.clone());
+
             let mut serialized = Vec::with_capacity(original.len());
-            Blob::serialize(original.as_slice(), &mut serialized, original.len()).unwrap();
+            blob.serialize(&mut serialized).unwrap();
 
-            let mut deserialized = Vec::with_capacity(original.len());
-            Blob::deserialize(serialized.as_slice(), &mut deserialized).unwrap();
+            let deserialized = Blob::deserialize(serialized.as_slice()).unwrap();
 
-            assert_eq!(original, deserialized);
+            assert_eq!(original, deserialized.content());
         }
     }
 }
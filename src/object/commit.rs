@@ -1,52 +1,73 @@
-use std::io::Write;
+use std::io::{Read, Write};
 
 use flate2::{write::ZlibEncoder as ZlibWriteEncoder, Compression};
 use sha1::{Digest, Sha1};
 
-use crate::repository::GitResult;
+use crate::repository::{GitError, GitResult};
 
-use super::Oid;
+use super::{object_header, parse_headers, split_once, ObjectKind, Oid, RawObject, Signature};
 
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Commit {
-    parents: Vec<Oid>,
     tree_id: Oid,
-    message: String,
-    author: String,
-    author_email: String,
-    commiter: String,
-    commiter_email: String,
+    parents: Vec<Oid>,
+    author: Signature,
+    commiter: Signature,
+    /// Headers after the committer line (encoding, gpgsig, mergetag, ...), in
+    /// their original order. Multi-line values are stored with their
+    /// continuation lines joined by a line feed
+    extra_headers: Vec<(String, Vec<u8>)>,
+    /// in the encoding given by the `encoding` header, utf8 by default
+    message: Vec<u8>,
 }
 
 impl Commit {
     pub fn new(
         parents: Vec<Oid>,
         tree_id: Oid,
-        mut message: String,
-        author: String,
-        author_email: String,
-        commiter: String,
-        commiter_email: String,
+        message: String,
+        author: Signature,
+        commiter: Signature,
     ) -> GitResult<Self> {
-        if message
-            .as_bytes()
-            .last()
-            .map(|&last| last != b'\n')
-            .unwrap_or_default()
-        {
-            message.push('\n')
+        let mut message = message.into_bytes();
+        if message.last().is_some_and(|&last| last != b'\n') {
+            message.push(b'\n')
         }
 
         Ok(Self {
-            parents,
             tree_id,
-            message,
+            parents,
             author,
-            author_email,
             commiter,
-            commiter_email,
+            extra_headers: Vec::new(),
+            message,
         })
     }
 
+    pub fn tree_id(&self) -> &Oid {
+        &self.tree_id
+    }
+
+    pub fn parents(&self) -> &[Oid] {
+        &self.parents
+    }
+
+    pub fn author(&self) -> &Signature {
+        &self.author
+    }
+
+    pub fn commiter(&self) -> &Signature {
+        &self.commiter
+    }
+
+    pub fn extra_headers(&self) -> &[(String, Vec<u8>)] {
+        &self.extra_headers
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
     pub fn hash(&self) -> Oid {
         let mut hasher = Sha1::new();
 
@@ -60,55 +81,115 @@ impl Commit {
 
     pub fn serialize_without_compression<W: Write>(&self, mut writer: W) -> GitResult<()> {
         writer.write_all(&self.header())?;
+        self.serialize_content(writer)
+    }
 
-        let tree_line = format!("tree {}\n", self.tree_id);
-        writer.write_all(tree_line.as_bytes())?;
+    pub fn serialize_content<W: Write>(&self, mut writer: W) -> GitResult<()> {
+        writeln!(writer, "tree {}", self.tree_id)?;
 
         for parent in &self.parents {
-            let parent_line = format!("parent {}\n", parent);
-            writer.write_all(parent_line.as_bytes())?;
+            writeln!(writer, "parent {}", parent)?;
         }
 
-        let timestamp = chrono::Local::now().format("%s %z");
-
-        let author_line = format!(
-            "author {} <{}> {}\n",
-            self.author, self.author_email, timestamp
-        );
-        writer.write_all(author_line.as_bytes())?;
+        writer.write_all(b"author ")?;
+        self.author.serialize(&mut writer)?;
+        writer.write_all(b"\ncommitter ")?;
+        self.commiter.serialize(&mut writer)?;
+        writer.write_all(b"\n")?;
 
-        let commiter_line = format!(
-            "committer {} <{}> {}\n\n",
-            self.commiter, self.commiter_email, timestamp
-        );
-        writer.write_all(commiter_line.as_bytes())?;
+        for (key, value) in &self.extra_headers {
+            write!(writer, "{key} ")?;
+            // continuation lines of multi-line values are prefixed with a space
+            for (number, line) in value.split(|&byte| byte == b'\n').enumerate() {
+                if number > 0 {
+                    writer.write_all(b"\n ")?;
+                }
+                writer.write_all(line)?;
+            }
+            writer.write_all(b"\n")?;
+        }
 
-        writer.write_all(self.message.as_bytes())?;
+        writer.write_all(b"\n")?;
+        writer.write_all(&self.message)?;
 
         Ok(())
     }
 
     pub fn serialize<W: Write>(&self, writer: W) -> GitResult<()> {
-        let encoder = ZlibWriteEncoder::new(writer, Compression::default());
+        let mut encoder = ZlibWriteEncoder::new(writer, Compression::default());
 
-        self.serialize_without_compression(encoder)?;
+        self.serialize_without_compression(&mut encoder)?;
+        encoder.finish()?;
 
         Ok(())
     }
 
+    pub fn deserialize<R: Read>(reader: R) -> GitResult<Self> {
+        let raw = RawObject::deserialize(reader)?;
+        if raw.kind() != ObjectKind::Commit {
+            return Err(GitError::UnexpectedObjectKind {
+                expected: ObjectKind::Commit,
+                found: raw.kind(),
+            });
+        }
+
+        Self::deserialize_content(raw.content())
+    }
+
+    pub fn deserialize_content(content: &[u8]) -> GitResult<Self> {
+        let (headers, message) =
+            split_once(content, b"\n\n").ok_or_else(|| malformed("missing message"))?;
+
+        let mut headers = parse_headers(headers, ObjectKind::Commit)?
+            .into_iter()
+            .peekable();
+
+        let tree_id = match headers.next() {
+            Some((key, value)) if key == "tree" => Oid::from_object_header(value)?,
+            _ => return Err(malformed("missing tree")),
+        };
+
+        let mut parents = Vec::new();
+        while let Some((_, value)) = headers.next_if(|(key, _)| key == "parent") {
+            parents.push(Oid::from_object_header(value)?);
+        }
+
+        let author = match headers.next() {
+            Some((key, value)) if key == "author" => Signature::parse(&value)?,
+            _ => return Err(malformed("missing author")),
+        };
+
+        let commiter = match headers.next() {
+            Some((key, value)) if key == "committer" => Signature::parse(&value)?,
+            _ => return Err(malformed("missing committer")),
+        };
+
+        Ok(Self {
+            tree_id,
+            parents,
+            author,
+            commiter,
+            extra_headers: headers.collect(),
+            message: message.to_vec(),
+        })
+    }
+
     pub fn header(&self) -> Vec<u8> {
-        format!("commit {}\0", self.size()).into_bytes()
+        object_header(ObjectKind::Commit, self.size())
     }
 
     fn size(&self) -> usize {
-        // 106 bytes are fixed, the rest is variable
-        // each parent line occupies 49 bytes
-        106 + self.author.as_bytes().len()
-            + self.author_email.as_bytes().len()
-            + self.commiter.as_bytes().len()
-            + self.commiter_email.as_bytes().len()
-            + self.message.as_bytes().len()
-            + self.parents.len() * 48
+        let mut content = Vec::new();
+        self.serialize_content(&mut content)
+            .expect("writing to vector cannot fail");
+        content.len()
+    }
+}
+
+fn malformed(reason: &str) -> GitError {
+    GitError::MalformedObject {
+        kind: ObjectKind::Commit,
+        reason: reason.to_string(),
     }
 }
 
@@ -129,10 +210,8 @@ mod tests {
             ],
             Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap(),
             "message".to_string(),
-            "John Doe".to_string(),
-            "johndoe@mail.com".to_string(),
-            "John Doe".to_string(),
-            "johndoe@mail.com".to_string(),
+            Signature::now("John Doe".to_string(), "johndoe@mail.com".to_string()).unwrap(),
+            Signature::now("John Doe".to_string(), "johndoe@mail.com".to_string()).unwrap(),
         )
         .unwrap();
 
@@ -148,4 +227,125 @@ mod tests {
 
         assert_eq!(decoded.len() - header.len(), commit.size())
     }
+
+    #[test]
+    pub fn hash_is_stable_for_explicit_signatures() {
+        let signature = Signature::new(
+            "John Doe".to_string(),
+            "johndoe@mail.com".to_string(),
+            1720539887,
+            -180,
+        )
+        .unwrap();
+        let commit = Commit::new(
+            vec![],
+            Oid::new("2e81171448eb9f2ee3821e3d447aa6b2fe3ddba1").unwrap(),
+            "First commit!".to_string(),
+            signature.clone(),
+            signature,
+        )
+        .unwrap();
+
+        let mut serialized = Vec::new();
+        commit.serialize(&mut serialized).unwrap();
+        let deserialized = Commit::deserialize(serialized.as_slice()).unwrap();
+
+        assert_eq!(commit, deserialized);
+        assert_eq!(
+            commit.hash().as_ref(),
+            "be61a307fd89b24112ea431ff5567b229e37e4a2"
+        );
+    }
+
+    #[test]
+    pub fn parsing_round_trips_byte_for_byte() {
+        let content = "\
+            tree f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc\n\
+            parent 554b0c91f951764bb11f1db849685d95b2c7a48f\n\
+            parent bedc28ca5099946b354104a3c6cc90ec20dbcaec\n\
+            author John Doe <johndoe@mail.com> 1720539887 -0300\n\
+            committer Jane Doe <janedoe@mail.com> 1720540000 +0530\n\
+            encoding UTF-8\n\
+            gpgsig -----BEGIN PGP SIGNATURE-----\n \n iQEzBAABCAAdFiEE\n -----END PGP SIGNATURE-----\n\
+            \n\
+            Merge branch\n\
+            \n\
+            With a body\n";
+
+        let commit = Commit::deserialize_content(content.as_bytes()).unwrap();
+
+        assert_eq!(commit.parents().len(), 2);
+        assert_eq!(commit.author().offset(), -180);
+        assert_eq!(commit.commiter().offset(), 330);
+        assert_eq!(commit.extra_headers().len(), 2);
+
+        let mut serialized = Vec::new();
+        commit.serialize_content(&mut serialized).unwrap();
+
+        assert_eq!(content, String::from_utf8(serialized).unwrap());
+        assert_eq!(
+            commit.hash().as_ref(),
+            "7f1536970582dc029c10f2c8a6f8cee7ec5de3be"
+        );
+    }
+
+    #[test]
+    pub fn parsing_keeps_other_encodings_and_negative_zero_offsets() {
+        let content = b"\
+            tree f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc\n\
+            author Jos\xe9 <jose@mail.com> 1720539887 -0000\n\
+            committer Jos\xe9 <jose@mail.com> 1720539887 +0000\n\
+            encoding ISO-8859-1\n\
+            \n\
+            Fran\xe7ais\n";
+
+        let commit = Commit::deserialize_content(content).unwrap();
+
+        assert_eq!(commit.author().name(), "Jos\u{fffd}");
+        assert_eq!(commit.message(), b"Fran\xe7ais\n");
+        assert_ne!(commit.author(), commit.commiter());
+
+        let mut serialized = Vec::new();
+        commit.serialize_content(&mut serialized).unwrap();
+
+        assert_eq!(serialized, content);
+    }
+
+    #[test]
+    pub fn parsing_keeps_unusual_dates_and_rejects_invalid_ones() {
+        let content = b"\
+            tree f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc\n\
+            author A <a@b> 0123 +0099\n\
+            committer A <a@b> 123 +0139\n\
+            \n\
+            message\n";
+
+        let commit = Commit::deserialize_content(content).unwrap();
+
+        assert_eq!(commit.author().time(), commit.commiter().time());
+        assert_eq!(commit.author().offset(), commit.commiter().offset());
+        let mut serialized = Vec::new();
+        commit.serialize_content(&mut serialized).unwrap();
+        assert_eq!(serialized, content);
+
+        for date in [
+            "123 ++100",
+            "123 +100",
+            "123 +01000",
+            "+123 +0100",
+            "12a +0100",
+        ] {
+            let content = format!(
+                "tree f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc\n\
+                author A <a@b> {date}\n\
+                committer A <a@b> 123 +0100\n\
+                \n\
+                message\n"
+            );
+            assert!(
+                Commit::deserialize_content(content.as_bytes()).is_err(),
+                "{date}"
+            );
+        }
+    }
 }
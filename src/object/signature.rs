@@ -0,0 +1,280 @@
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, Offset, TimeZone};
+
+use crate::repository::{GitError, GitResult};
+
+use super::split_once;
+
+/// Identity and moment of an action, as in `John Doe <johndoe@mail.com> 1720539887 -0300`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Signature {
+    /// bytes in the encoding of the object, usually utf8
+    name: Vec<u8>,
+    email: Vec<u8>,
+    /// seconds since unix epoch
+    time: i64,
+    /// offset from UTC in minutes
+    offset: i32,
+    /// `<time> <offset>` as written, since the same moment can be spelled in
+    /// several ways, such as `-0000` or with zero padding
+    date: String,
+}
+
+impl Signature {
+    /// Fails if the name or email contain `<`, `>` or a newline, which would
+    /// let them forge the rest of the header
+    pub fn new(name: String, email: String, time: i64, offset: i32) -> GitResult<Self> {
+        let forbidden = |c: char| matches!(c, '<' | '>' | '\n');
+        if name.contains(forbidden) || email.contains(forbidden) {
+            let signature = format!("{name} <{email}>");
+            return Err(GitError::InvalidSignature(
+                signature.escape_debug().to_string(),
+            ));
+        }
+
+        let sign = if offset < 0 { '-' } else { '+' };
+        let date = format!(
+            "{time} {sign}{:02}{:02}",
+            offset.abs() / 60,
+            offset.abs() % 60
+        );
+
+        Ok(Self {
+            name: name.into_bytes(),
+            email: email.into_bytes(),
+            time,
+            offset,
+            date,
+        })
+    }
+
+    pub fn now(name: String, email: String) -> GitResult<Self> {
+        let now = Local::now();
+        let offset = now.offset().fix().local_minus_utc() / 60;
+        Self::new(name, email, now.timestamp(), offset)
+    }
+
+    /// Builds a signature for the given date, in any of the formats accepted by
+    /// `parse_date`, falling back to the current time if there is none
+    pub fn with_date(name: String, email: String, date: Option<&str>) -> GitResult<Self> {
+        match date {
+            Some(date) => {
+                let (time, offset) = parse_date(date)?;
+                Self::new(name, email, time, offset)
+            }
+            None => Self::now(name, email),
+        }
+    }
+
+    /// Parses a signature as stored in an object. Stored objects are read as
+    /// they are, even if they would be rejected when written
+    pub fn parse(signature: &[u8]) -> GitResult<Self> {
+        let invalid = || GitError::InvalidSignature(String::from_utf8_lossy(signature).to_string());
+
+        let (name, rest) = split_once(signature, b" <").ok_or_else(invalid)?;
+        let (email, date) = split_once(rest, b"> ").ok_or_else(invalid)?;
+        let date = std::str::from_utf8(date).map_err(|_| invalid())?;
+        let (time, offset) = date.split_once(' ').ok_or_else(invalid)?;
+
+        let digits = time.strip_prefix('-').unwrap_or(time);
+        if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let time = time.parse().map_err(|_| invalid())?;
+        let offset = parse_offset(offset).ok_or_else(invalid)?;
+
+        Ok(Self {
+            name: name.to_vec(),
+            email: email.to_vec(),
+            time,
+            offset,
+            date: date.to_string(),
+        })
+    }
+
+    /// The name, with bytes which are not utf8 replaced
+    pub fn name(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    pub fn email(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.email)
+    }
+
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// Writes the signature as stored in objects, byte for byte
+    pub fn serialize<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.name)?;
+        writer.write_all(b" <")?;
+        writer.write_all(&self.email)?;
+        writer.write_all(b"> ")?;
+        writer.write_all(self.date.as_bytes())
+    }
+}
+
+impl FromStr for Signature {
+    type Err = GitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s.as_bytes())
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut serialized = Vec::new();
+        self.serialize(&mut serialized)
+            .expect("writing to vector cannot fail");
+        String::from_utf8_lossy(&serialized).fmt(f)
+    }
+}
+
+/// Parses a date as accepted by `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE`,
+/// returning seconds since epoch and the offset from UTC in minutes.
+///
+/// Supported formats are:
+///
+/// - internal: `1720539887 -0300`, optionally prefixed with `@`
+/// - RFC 2822: `Tue, 9 Jul 2024 12:44:47 -0300`
+/// - ISO 8601: `2024-07-09T12:44:47-03:00` or `2024-07-09 12:44:47 -0300`
+///
+/// Dates without an explicit offset are interpreted in the local timezone
+pub fn parse_date(date: &str) -> GitResult<(i64, i32)> {
+    let invalid = || GitError::InvalidDate(date.to_string());
+    let date = date.trim();
+
+    let internal = date.strip_prefix('@').unwrap_or(date);
+    if let Some((time, offset)) = internal.split_once(' ') {
+        if let (Ok(time), Some(offset)) = (time.parse(), parse_offset(offset)) {
+            return Ok((time, offset));
+        }
+    } else if let Ok(time) = internal.parse() {
+        if date.starts_with('@') {
+            return Ok((time, 0));
+        }
+    }
+
+    let with_offset = DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z"))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"));
+    if let Ok(parsed) = with_offset {
+        return Ok((parsed.timestamp(), parsed.offset().local_minus_utc() / 60));
+    }
+
+    let naive = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S"))
+        .map_err(|_| invalid())?;
+    let local = Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(invalid)?;
+
+    Ok((
+        local.timestamp(),
+        local.offset().fix().local_minus_utc() / 60,
+    ))
+}
+
+/// Parses the cutoff of options like `--prune` and `--expire`, returning
+/// seconds since epoch, or `None` for `never`. Besides the formats of
+/// `parse_date`, it accepts `now`, `all` and relative dates like `2.weeks.ago`
+pub fn parse_expiry_date(date: &str) -> GitResult<Option<i64>> {
+    let now = Local::now().timestamp();
+
+    match date.trim() {
+        "never" | "false" => return Ok(None),
+        "now" => return Ok(Some(now)),
+        "all" => return Ok(Some(i64::MAX)),
+        _ => (),
+    }
+
+    let words: Vec<&str> = date.trim().split(['.', ' ']).collect();
+    if let [count, unit, "ago"] = words.as_slice() {
+        let count: i64 = count
+            .parse()
+            .map_err(|_| GitError::InvalidDate(date.to_string()))?;
+        let seconds = match unit.trim_end_matches('s') {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return Err(GitError::InvalidDate(date.to_string())),
+        };
+        return Ok(Some(now - count * seconds));
+    }
+
+    parse_date(date).map(|(time, _)| Some(time))
+}
+
+/// Parses a timezone offset in the `+HHMM` format into minutes
+fn parse_offset(offset: &str) -> Option<i32> {
+    let (sign, digits) = offset.split_at_checked(1)?;
+    if digits.len() != 4 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let sign = match sign {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn parses_supported_date_formats() {
+        let expected = (1720539887, -180);
+
+        assert_eq!(parse_date("1720539887 -0300").unwrap(), expected);
+        assert_eq!(parse_date("@1720539887 -0300").unwrap(), expected);
+        assert_eq!(
+            parse_date("Tue, 9 Jul 2024 12:44:47 -0300").unwrap(),
+            expected
+        );
+        assert_eq!(parse_date("2024-07-09T12:44:47-03:00").unwrap(), expected);
+        assert_eq!(parse_date("2024-07-09 12:44:47 -0300").unwrap(), expected);
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    pub fn parses_expiry_dates() {
+        let now = Local::now().timestamp();
+
+        assert_eq!(parse_expiry_date("never").unwrap(), None);
+        assert_eq!(
+            parse_expiry_date("1720539887 -0300").unwrap(),
+            Some(1720539887)
+        );
+
+        let two_weeks_ago = parse_expiry_date("2.weeks.ago").unwrap().unwrap();
+        assert!((now - 14 * 24 * 60 * 60 - two_weeks_ago).abs() <= 1);
+        assert!(parse_expiry_date("2.fortnights.ago").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::{Read, Write};
+
+use flate2::{write::ZlibEncoder as ZlibWriteEncoder, Compression};
+use sha1::{Digest, Sha1};
+
+use crate::repository::{GitError, GitResult};
+
+use super::{object_header, parse_headers, split_once, ObjectKind, Oid, RawObject, Signature};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Tag {
+    object_id: Oid,
+    object_kind: ObjectKind,
+    name: String,
+    /// very old tags were created without a tagger
+    tagger: Option<Signature>,
+    /// not necessarily utf8, as tags have no encoding header
+    message: Vec<u8>,
+}
+
+impl Tag {
+    pub fn new(
+        object_id: Oid,
+        object_kind: ObjectKind,
+        name: String,
+        tagger: Signature,
+        message: String,
+    ) -> GitResult<Self> {
+        let mut message = message.into_bytes();
+        if message.last().is_some_and(|&last| last != b'\n') {
+            message.push(b'\n')
+        }
+
+        Ok(Self {
+            object_id,
+            object_kind,
+            name,
+            tagger: Some(tagger),
+            message,
+        })
+    }
+
+    pub fn object_id(&self) -> &Oid {
+        &self.object_id
+    }
+
+    pub fn object_kind(&self) -> ObjectKind {
+        self.object_kind
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tagger(&self) -> Option<&Signature> {
+        self.tagger.as_ref()
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    pub fn hash(&self) -> Oid {
+        let mut hasher = Sha1::new();
+
+        self.serialize_without_compression(&mut hasher)
+            .expect("writing to hasher cannot fail");
+
+        let raw_id = hasher.finalize().into();
+
+        Oid::from_raw_bytes(raw_id)
+    }
+
+    pub fn serialize_without_compression<W: Write>(&self, mut writer: W) -> GitResult<()> {
+        writer.write_all(&self.header())?;
+        self.serialize_content(writer)
+    }
+
+    pub fn serialize_content<W: Write>(&self, mut writer: W) -> GitResult<()> {
+        writeln!(writer, "object {}", self.object_id)?;
+        writeln!(writer, "type {}", self.object_kind)?;
+        writeln!(writer, "tag {}", self.name)?;
+
+        if let Some(tagger) = &self.tagger {
+            writer.write_all(b"tagger ")?;
+            tagger.serialize(&mut writer)?;
+            writer.write_all(b"\n")?;
+        }
+
+        writer.write_all(b"\n")?;
+        writer.write_all(&self.message)?;
+
+        Ok(())
+    }
+
+    pub fn serialize<W: Write>(&self, writer: W) -> GitResult<()> {
+        let mut encoder = ZlibWriteEncoder::new(writer, Compression::default());
+
+        self.serialize_without_compression(&mut encoder)?;
+        encoder.finish()?;
+
+        Ok(())
+    }
+
+    pub fn deserialize<R: Read>(reader: R) -> GitResult<Self> {
+        let raw = RawObject::deserialize(reader)?;
+        if raw.kind() != ObjectKind::Tag {
+            return Err(GitError::UnexpectedObjectKind {
+                expected: ObjectKind::Tag,
+                found: raw.kind(),
+            });
+        }
+
+        Self::deserialize_content(raw.content())
+    }
+
+    pub fn deserialize_content(content: &[u8]) -> GitResult<Self> {
+        let (headers, message) =
+            split_once(content, b"\n\n").ok_or_else(|| malformed("missing message"))?;
+        let utf8 = |value: Vec<u8>| String::from_utf8(value).map_err(|_| malformed("not utf8"));
+
+        let mut headers = parse_headers(headers, ObjectKind::Tag)?.into_iter();
+
+        let object_id = match headers.next() {
+            Some((key, value)) if key == "object" => Oid::from_object_header(value)?,
+            _ => return Err(malformed("missing object")),
+        };
+
+        let object_kind = match headers.next() {
+            Some((key, value)) if key == "type" => utf8(value)?.parse()?,
+            _ => return Err(malformed("missing type")),
+        };
+
+        let name = match headers.next() {
+            Some((key, value)) if key == "tag" => utf8(value)?,
+            _ => return Err(malformed("missing tag name")),
+        };
+
+        let tagger = match headers.next() {
+            Some((key, value)) if key == "tagger" => Some(Signature::parse(&value)?),
+            None => None,
+            Some((key, _)) => return Err(malformed(&format!("unexpected header {key}"))),
+        };
+
+        if let Some((key, _)) = headers.next() {
+            return Err(malformed(&format!("unexpected header {key}")));
+        }
+
+        Ok(Self {
+            object_id,
+            object_kind,
+            name,
+            tagger,
+            message: message.to_vec(),
+        })
+    }
+
+    pub fn header(&self) -> Vec<u8> {
+        object_header(ObjectKind::Tag, self.size())
+    }
+
+    fn size(&self) -> usize {
+        let mut content = Vec::new();
+        self.serialize_content(&mut content)
+            .expect("writing to vector cannot fail");
+        content.len()
+    }
+}
+
+fn malformed(reason: &str) -> GitError {
+    GitError::MalformedObject {
+        kind: ObjectKind::Tag,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn can_serialize_and_deserialize() {
+        let tagger = Signature::new(
+            "John Doe".to_string(),
+            "johndoe@mail.com".to_string(),
+            1720539887,
+            -180,
+        )
+        .unwrap();
+        let tag = Tag::new(
+            Oid::new("be61a307fd89b24112ea431ff5567b229e37e4a2").unwrap(),
+            ObjectKind::Commit,
+            "v1.0".to_string(),
+            tagger,
+            "First release".to_string(),
+        )
+        .unwrap();
+
+        let mut serialized = Vec::new();
+        tag.serialize(&mut serialized).unwrap();
+        let deserialized = Tag::deserialize(serialized.as_slice()).unwrap();
+
+        assert_eq!(tag, deserialized);
+        assert_eq!(
+            tag.hash().as_ref(),
+            "78d28b3ee5f0851ca617c9d92f8382aaa198de24"
+        );
+    }
+}
@@ -1,13 +1,21 @@
-use std::io::{self, BufRead, BufReader, Read, Write};
-
-use flate2::{
-    read::ZlibDecoder as ZlibReadDecoder, write::ZlibEncoder as ZlibWriteEncoder, Compression,
+use std::{
+    cmp::Ordering,
+    io::{BufRead, Read, Write},
 };
+
+use flate2::{write::ZlibEncoder as ZlibWriteEncoder, Compression};
 use sha1::{Digest, Sha1};
 
-use crate::{repository::GitResult, utils::extract_bits};
+use crate::repository::{GitError, GitResult};
 
-use super::{Oid, RawOid};
+use super::{object_header, ObjectKind, Oid, RawObject, RawOid};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+/// submodules are stored as a commit id with a mode of their own
+const S_IFGITLINK: u32 = 0o160000;
 
 #[derive(Default, PartialEq, Eq, Debug)]
 pub struct Tree {
@@ -15,8 +23,29 @@ pub struct Tree {
 }
 
 impl Tree {
-    pub fn new(entries: Vec<TreeEntry>) -> Self {
-        Self { entries }
+    /// Creates a tree with its entries in canonical order, failing if two of them share a name
+    pub fn new(mut entries: Vec<TreeEntry>) -> GitResult<Self> {
+        entries.sort_by(TreeEntry::canonical_cmp);
+
+        if let Some(duplicate) = duplicate_name(&entries) {
+            return Err(GitError::DuplicateTreeEntry(duplicate.to_string()));
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Returns whether entries are sorted as git requires, without duplicates
+    pub fn is_canonical(&self) -> bool {
+        let is_sorted = self
+            .entries
+            .windows(2)
+            .all(|pair| pair[0].canonical_cmp(&pair[1]) == Ordering::Less);
+
+        is_sorted && duplicate_name(&self.entries).is_none()
+    }
+
+    pub fn entries(&self) -> &[TreeEntry] {
+        &self.entries
     }
 
     pub fn hash(&self) -> Oid {
@@ -25,11 +54,8 @@ impl Tree {
 
         hasher.update(&header);
 
-        for entry in &self.entries {
-            entry
-                .serialize(&mut hasher)
-                .expect("writing to hasher cannot fail");
-        }
+        self.serialize_content(&mut hasher)
+            .expect("writing to hasher cannot fail");
 
         let raw_id = hasher.finalize().into();
 
@@ -40,32 +66,46 @@ impl Tree {
         let mut encoder = ZlibWriteEncoder::new(writer, Compression::default());
 
         encoder.write_all(&self.header())?;
+        self.serialize_content(&mut encoder)?;
+        encoder.finish()?;
+
+        Ok(())
+    }
 
+    pub fn serialize_content<W: Write>(&self, mut writer: W) -> GitResult<()> {
         for entry in &self.entries {
-            entry.serialize(&mut encoder)?;
+            entry.serialize(&mut writer)?;
         }
 
         Ok(())
     }
 
     pub fn deserialize<R: Read>(reader: R) -> GitResult<Self> {
-        let mut decoder = BufReader::new(ZlibReadDecoder::new(reader));
+        let raw = RawObject::deserialize(reader)?;
+        if raw.kind() != ObjectKind::Tree {
+            return Err(GitError::UnexpectedObjectKind {
+                expected: ObjectKind::Tree,
+                found: raw.kind(),
+            });
+        }
 
-        let mut header_bytes = Vec::new();
-        decoder.read_until(b'\0', &mut header_bytes)?;
+        Self::deserialize_content(raw.content())
+    }
 
+    pub fn deserialize_content(mut content: &[u8]) -> GitResult<Self> {
         let mut entries = Vec::new();
 
-        while let Ok(entry) = TreeEntry::deserialize(&mut decoder) {
-            entries.push(entry);
+        while !content.is_empty() {
+            entries.push(TreeEntry::deserialize(&mut content)?);
         }
 
-        Ok(Self::new(entries))
+        // entries are kept as stored, so that trees hash to the id they were read from
+        Ok(Self { entries })
     }
 
-    fn header(&self) -> Vec<u8> {
+    pub fn header(&self) -> Vec<u8> {
         let tree_size: usize = self.entries.iter().map(|entry| entry.size()).sum();
-        format!("tree {}\0", tree_size).bytes().collect()
+        object_header(ObjectKind::Tree, tree_size)
     }
 }
 
@@ -77,72 +117,44 @@ pub struct TreeEntry {
 }
 
 impl TreeEntry {
+    /// Creates an entry, normalizing the mode to one of the few that git
+    /// stores in trees (`100644`, `100755`, `120000`, `160000` or `40000`)
     pub fn new(mode: u32, name: String, oid: Oid) -> GitResult<Self> {
+        let mode = normalize_mode(mode)?;
+
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+            return Err(GitError::InvalidTreeEntryName(name));
+        }
+
         Ok(Self { mode, name, oid })
     }
 
     pub fn serialize<W: Write>(&self, mut writer: W) -> GitResult<()> {
-        let file_type_1 = extract_bits(self.mode, 0o100000, 15) as u8 + b'0';
-        let file_type_2 = extract_bits(self.mode, 0o70000, 12) as u8 + b'0';
-        let special = extract_bits(self.mode, 0o7000, 9) as u8 + b'0';
-        let owner = extract_bits(self.mode, 0o700, 6) as u8 + b'0';
-        let group = extract_bits(self.mode, 0o70, 3) as u8 + b'0';
-        let others = extract_bits(self.mode, 0o7, 0) as u8 + b'0';
-
-        writer.write_all(&file_type_1.to_be_bytes())?;
-        writer.write_all(&file_type_2.to_be_bytes())?;
-        writer.write_all(&special.to_be_bytes())?;
-        writer.write_all(&owner.to_be_bytes())?;
-        writer.write_all(&group.to_be_bytes())?;
-        writer.write_all(&others.to_be_bytes())?;
-        writer.write_all(&[b' '])?;
+        write!(writer, "{:o} ", self.mode)?;
         writer.write_all(self.name.as_bytes())?;
-        writer.write_all(&[b'\0'])?;
+        writer.write_all(b"\0")?;
         writer.write_all(&self.oid.to_raw_bytes())?;
 
         Ok(())
     }
 
     pub fn deserialize<R: BufRead>(mut reader: R) -> GitResult<Self> {
-        let mut file_type_1_byte = [0; 1];
-        reader.read_exact(&mut file_type_1_byte)?;
-        let file_type_1 = u8::from_be_bytes(file_type_1_byte) - b'0';
-
-        let mut file_type_2_byte = [0; 1];
-        reader.read_exact(&mut file_type_2_byte)?;
-        let file_type_2 = u8::from_be_bytes(file_type_2_byte) - b'0';
-
-        let mut special_byte = [0; 1];
-        reader.read_exact(&mut special_byte)?;
-        let special = u8::from_be_bytes(special_byte) - b'0';
-
-        let mut owner_byte = [0; 1];
-        reader.read_exact(&mut owner_byte)?;
-        let owner = u8::from_be_bytes(owner_byte) - b'0';
-
-        let mut group_byte = [0; 1];
-        reader.read_exact(&mut group_byte)?;
-        let group = u8::from_be_bytes(group_byte) - b'0';
-
-        let mut others_byte = [0; 1];
-        reader.read_exact(&mut others_byte)?;
-        let others = u8::from_be_bytes(others_byte) - b'0';
-
-        let mode = ((file_type_1 as u32) << 15)
-            | ((file_type_2 as u32) << 12)
-            | ((special as u32) << 9)
-            | ((owner as u32) << 6)
-            | ((group as u32) << 3)
-            | (others as u32);
-
-        let mut garbage = [0; 1];
-        reader.read_exact(&mut garbage)?;
+        let mut mode = Vec::new();
+        reader.read_until(b' ', &mut mode)?;
+        if mode.pop() != Some(b' ') {
+            return Err(malformed("missing mode"));
+        }
+        let mode = std::str::from_utf8(&mode)
+            .ok()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .ok_or_else(|| malformed("invalid mode"))?;
 
         let mut name = Vec::new();
         reader.read_until(b'\0', &mut name)?;
-        name.pop();
-        let name =
-            String::from_utf8(name).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        if name.pop() != Some(b'\0') {
+            return Err(malformed("missing name"));
+        }
+        let name = String::from_utf8(name).map_err(|_| malformed("name is not utf8"))?;
 
         let mut raw_oid = RawOid::default();
         reader.read_exact(&mut raw_oid)?;
@@ -151,9 +163,76 @@ impl TreeEntry {
         Ok(Self { mode, name, oid })
     }
 
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn oid(&self) -> &Oid {
+        &self.oid
+    }
+
+    /// Type of the object the entry points to, as derived from its mode
+    pub fn kind(&self) -> ObjectKind {
+        match self.mode & 0o170000 {
+            0o040000 => ObjectKind::Tree,
+            0o160000 => ObjectKind::Commit,
+            _ => ObjectKind::Blob,
+        }
+    }
+
     pub fn size(&self) -> usize {
-        // 28 bytes are fixed, entry name is variable
-        28 + self.name.len()
+        // octal mode and name are variable, followed by a space, a null and 20 bytes of id
+        format!("{:o}", self.mode).len() + self.name.len() + 22
+    }
+
+    /// Compares entries as git does, where directories sort as if their name
+    /// was followed by a slash
+    pub fn canonical_cmp(&self, other: &Self) -> Ordering {
+        let self_name = self.name.as_bytes().iter().chain(self.directory_suffix());
+        let other_name = other.name.as_bytes().iter().chain(other.directory_suffix());
+
+        self_name.cmp(other_name)
+    }
+
+    fn directory_suffix(&self) -> &'static [u8] {
+        match self.kind() {
+            ObjectKind::Tree => b"/",
+            _ => b"",
+        }
+    }
+}
+
+/// Finds a name used by more than one entry, even if for different object types
+fn duplicate_name(entries: &[TreeEntry]) -> Option<&str> {
+    let mut names: Vec<&str> = entries.iter().map(|entry| entry.name()).collect();
+    names.sort_unstable();
+
+    names
+        .windows(2)
+        .find(|pair| pair[0] == pair[1])
+        .map(|pair| pair[0])
+}
+
+/// Maps any file system mode to the canonical mode stored in trees and the index
+pub fn normalize_mode(mode: u32) -> GitResult<u32> {
+    match mode & S_IFMT {
+        S_IFDIR => Ok(0o40000),
+        S_IFLNK => Ok(0o120000),
+        S_IFGITLINK => Ok(0o160000),
+        S_IFREG if mode & 0o100 != 0 => Ok(0o100755),
+        S_IFREG => Ok(0o100644),
+        _ => Err(GitError::InvalidFileMode(mode)),
+    }
+}
+
+fn malformed(reason: &str) -> GitError {
+    GitError::MalformedObject {
+        kind: ObjectKind::Tree,
+        reason: reason.to_string(),
     }
 }
 
@@ -184,7 +263,7 @@ mod tests {
                 oid: Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap(),
             },
         ];
-        let tree = Tree::new(entries);
+        let tree = Tree::new(entries).unwrap();
 
         let mut serialized = Vec::new();
         tree.serialize(&mut serialized).unwrap();
@@ -195,6 +274,48 @@ mod tests {
         assert_eq!(tree, deserialized);
     }
 
+    #[test]
+    pub fn hashes_identically_to_git() {
+        let entry = |mode, name: &str, id| {
+            TreeEntry::new(mode, name.to_string(), Oid::new(id).unwrap()).unwrap()
+        };
+
+        // given in index order and with raw file system modes
+        let tree = Tree::new(vec![
+            entry(0o40755, "src", "ec64d651b31ad51d16b2f02cdea80d4c960dd866"),
+            entry(
+                0o100664,
+                "src.rs",
+                "b68025345d5301abad4d9ec9166f455243a0d746",
+            ),
+            entry(
+                0o100664,
+                "a.txt",
+                "78981922613b2afb6025042ff6bd878ac1994e85",
+            ),
+            entry(0o40755, "docs", "014e4d54262f9f4533dad4381c41c3dd9d557a9e"),
+            entry(0o100600, "a-b", "61780798228d17af2d34fce4cfbdf35556832472"),
+        ])
+        .unwrap();
+
+        assert!(tree.is_canonical());
+        assert_eq!(
+            tree.hash().as_ref(),
+            "0676a04c224495b7295717efd1af9910e7753437"
+        );
+    }
+
+    #[test]
+    pub fn rejects_duplicate_names() {
+        let oid = Oid::new("f0133c7517d34d37f8dca8c8444c6a9cdd7e4cdc").unwrap();
+        let tree = Tree::new(vec![
+            TreeEntry::new(0o100644, "name".to_string(), oid.clone()).unwrap(),
+            TreeEntry::new(0o40000, "name".to_string(), oid).unwrap(),
+        ]);
+
+        assert!(matches!(tree, Err(GitError::DuplicateTreeEntry(_))));
+    }
+
     #[test]
     pub fn size_calculation_is_correct() {
         let entry = TreeEntry {
@@ -0,0 +1,322 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use flate2::bufread::ZlibDecoder;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    object::{ObjectKind, Oid, RawObject, RawOid, OID_HEX_LEN},
+    repository::{GitError, GitResult},
+    utils::read_offset_varint,
+};
+
+mod delta;
+mod index;
+mod writer;
+
+pub use delta::{apply_delta, create_delta, delta_result_size};
+pub use index::{PackIndex, PackIndexEntry};
+pub use writer::{write_pack, DeltaOptions};
+
+const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+
+/// deltas whose chain is longer than this are considered corrupt, as they most likely form a cycle
+const MAX_DELTA_DEPTH: usize = 10000;
+
+/// How an entry of the pack is stored
+enum PackEntry {
+    Object(ObjectKind),
+    /// delta against the entry at the given offset of the same pack
+    OfsDelta(u64),
+    /// delta against the object with the given id
+    RefDelta(Oid),
+}
+
+/// A packfile together with its index
+pub struct Pack {
+    path: PathBuf,
+    index: PackIndex,
+}
+
+impl Pack {
+    /// Opens the pack that belongs to the given `.idx` file
+    pub fn open<P: AsRef<Path>>(index_path: P) -> GitResult<Self> {
+        let index = PackIndex::open(&index_path)?;
+        let path = index_path.as_ref().with_extension("pack");
+
+        let mut reader = File::open(&path)?;
+        let mut signature = [0; 4];
+        reader.read_exact(&mut signature)?;
+        let mut version = [0; 4];
+        reader.read_exact(&mut version)?;
+
+        if &signature != PACK_SIGNATURE || !matches!(u32::from_be_bytes(version), 2 | 3) {
+            return Err(GitError::InvalidPack(format!(
+                "{}: invalid header",
+                path.display()
+            )));
+        }
+
+        Ok(Self { path, index })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    pub fn contains(&self, oid: &Oid) -> GitResult<bool> {
+        Ok(self.index.find(oid)?.is_some())
+    }
+
+    pub fn read_object(&self, oid: &Oid) -> GitResult<Option<RawObject>> {
+        match self.index.find(oid)? {
+            Some(offset) => self.read_at(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn read_object_header(&self, oid: &Oid) -> GitResult<Option<(ObjectKind, usize)>> {
+        match self.index.find(oid)? {
+            Some(offset) => self.read_header_at(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks the trailing checksum of the pack against its content and its
+    /// index, as well as the checksum of the index itself
+    pub fn verify_checksum(&self) -> GitResult<()> {
+        self.index.verify_checksum()?;
+
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let data_size = reader
+            .get_ref()
+            .metadata()?
+            .len()
+            .checked_sub(OID_HEX_LEN as u64)
+            .ok_or_else(|| self.invalid("truncated pack"))?;
+
+        let mut hasher = Sha1::new();
+        io::copy(&mut (&mut reader).take(data_size), &mut hasher)?;
+
+        let mut checksum = RawOid::default();
+        reader.read_exact(&mut checksum)?;
+
+        if hasher.finalize().as_slice() != checksum {
+            return Err(self.invalid("pack checksum mismatch"));
+        }
+        if self.index.pack_checksum()? != checksum {
+            return Err(self.invalid("pack does not match its index"));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the object stored at the given offset, resolving its delta chain
+    pub fn read_at(&self, offset: u64) -> GitResult<RawObject> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+
+        let mut deltas = Vec::new();
+        let mut offset = offset;
+
+        let (kind, mut content) = loop {
+            let (entry, size) = self.read_entry_header(&mut reader, offset)?;
+            let data = inflate(&mut reader, size)?;
+
+            offset = match entry {
+                PackEntry::Object(kind) => break (kind, data),
+                PackEntry::OfsDelta(base_offset) => base_offset,
+                PackEntry::RefDelta(base_id) => self.find_base(&base_id)?,
+            };
+
+            deltas.push(data);
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(self.invalid("delta chain too long"));
+            }
+        };
+
+        for delta in deltas.iter().rev() {
+            content = apply_delta(&content, delta)?;
+        }
+
+        Ok(RawObject::new(kind, content))
+    }
+
+    /// Reads type and size of the object at the given offset. The size of a
+    /// delta is read from its own header, only its type requires walking the chain
+    pub fn read_header_at(&self, offset: u64) -> GitResult<(ObjectKind, usize)> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+
+        let mut result_size = None;
+        let mut offset = offset;
+
+        for _ in 0..MAX_DELTA_DEPTH {
+            let (entry, size) = self.read_entry_header(&mut reader, offset)?;
+
+            offset = match entry {
+                PackEntry::Object(kind) => return Ok((kind, result_size.unwrap_or(size))),
+                PackEntry::OfsDelta(base_offset) => base_offset,
+                PackEntry::RefDelta(base_id) => self.find_base(&base_id)?,
+            };
+
+            if result_size.is_none() {
+                // both sizes are at most 10 bytes long each
+                let mut delta_header = Vec::with_capacity(20);
+                ZlibDecoder::new(&mut reader)
+                    .take(20)
+                    .read_to_end(&mut delta_header)?;
+                result_size = Some(delta_result_size(&delta_header)?);
+            }
+        }
+
+        Err(self.invalid("delta chain too long"))
+    }
+
+    /// Reads the type and size that prefix every entry, leaving the reader at
+    /// the start of its compressed data
+    fn read_entry_header<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offset: u64,
+    ) -> GitResult<(PackEntry, usize)> {
+        reader.seek(SeekFrom::Start(offset))?;
+
+        // first byte holds a continuation bit, 3 bits of type and 4 bits of
+        // size, following bytes hold a continuation bit and 7 bits of size
+        let mut byte = read_byte(reader)?;
+        let kind = (byte >> 4) & 0b111;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            if shift >= usize::BITS {
+                return Err(self.invalid("entry size too large"));
+            }
+            byte = read_byte(reader)?;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let entry = match kind {
+            1 => PackEntry::Object(ObjectKind::Commit),
+            2 => PackEntry::Object(ObjectKind::Tree),
+            3 => PackEntry::Object(ObjectKind::Blob),
+            4 => PackEntry::Object(ObjectKind::Tag),
+            6 => {
+                // negative offset from the start of this entry
+                let distance = read_offset_varint(reader).map_err(|error| match error.kind() {
+                    io::ErrorKind::InvalidData => self.invalid("delta base offset too large"),
+                    _ => error.into(),
+                })?;
+
+                let base_offset = offset
+                    .checked_sub(distance)
+                    .filter(|_| distance != 0)
+                    .ok_or_else(|| self.invalid("delta base offset out of bounds"))?;
+                PackEntry::OfsDelta(base_offset)
+            }
+            7 => {
+                let mut raw_oid = RawOid::default();
+                reader.read_exact(&mut raw_oid)?;
+                PackEntry::RefDelta(Oid::from_raw_bytes(raw_oid))
+            }
+            _ => return Err(self.invalid("invalid entry type")),
+        };
+
+        Ok((entry, size))
+    }
+
+    fn find_base(&self, base_id: &Oid) -> GitResult<u64> {
+        self.index
+            .find(base_id)?
+            .ok_or_else(|| GitError::ObjectNotFound(base_id.clone()))
+    }
+
+    fn invalid(&self, reason: &str) -> GitError {
+        GitError::InvalidPack(format!("{}: {}", self.path.display(), reason))
+    }
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> GitResult<u8> {
+    let mut byte = [0; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Inflates the entry data at the current position, which must have the given size
+fn inflate<R: std::io::BufRead>(reader: &mut R, size: usize) -> GitResult<Vec<u8>> {
+    // the size comes from the pack, so it only bounds how much is inflated
+    let mut data = Vec::new();
+    ZlibDecoder::new(reader)
+        .take((size as u64).saturating_add(1))
+        .read_to_end(&mut data)?;
+
+    if data.len() != size {
+        return Err(GitError::ObjectSizeMismatch {
+            expected: size,
+            found: data.len(),
+        });
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Writes a pack holding a single raw entry, at offset 12
+    fn write_raw_pack(dir: &Path, name: &str, entry: &[u8]) -> Pack {
+        let mut content = b"PACK".to_vec();
+        content.extend_from_slice(&2u32.to_be_bytes());
+        content.extend_from_slice(&1u32.to_be_bytes());
+        content.extend_from_slice(entry);
+        fs::write(dir.join(format!("{name}.pack")), content).unwrap();
+
+        let oid = Oid::new("1000000000000000000000000000000000000000").unwrap();
+        let index_path = dir.join(format!("{name}.idx"));
+        PackIndex::serialize(
+            &[(oid, 12, 0)],
+            &RawOid::default(),
+            File::create(&index_path).unwrap(),
+        )
+        .unwrap();
+
+        Pack::open(index_path).unwrap()
+    }
+
+    #[test]
+    pub fn overlong_sizes_and_offsets_are_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // a blob whose size never ends
+        let mut entry = vec![0xb0];
+        entry.extend_from_slice(&[0xff; 16]);
+        let pack = write_raw_pack(dir.path(), "size", &entry);
+        assert!(matches!(pack.read_at(12), Err(GitError::InvalidPack(_))));
+        assert!(matches!(
+            pack.read_header_at(12),
+            Err(GitError::InvalidPack(_))
+        ));
+
+        // a delta whose base offset never ends
+        let mut entry = vec![0x60];
+        entry.extend_from_slice(&[0xff; 16]);
+        let pack = write_raw_pack(dir.path(), "offset", &entry);
+        assert!(matches!(pack.read_at(12), Err(GitError::InvalidPack(_))));
+
+        let mut delta = vec![0xff; 16];
+        delta.push(0);
+        assert!(matches!(
+            delta_result_size(&delta),
+            Err(GitError::InvalidDelta(_))
+        ));
+    }
+}
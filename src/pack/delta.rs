@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use crate::repository::{GitError, GitResult};
+
+/// length of the base blocks indexed when looking for copies
+const BLOCK_SIZE: usize = 16;
+/// largest size a single copy instruction can encode
+const MAX_COPY_SIZE: usize = 0xffffff;
+/// largest number of literal bytes a single insert instruction can hold
+const MAX_INSERT_SIZE: usize = 0x7f;
+
+/// Rebuilds an object by applying a delta, as stored in packfiles, to its base.
+///
+/// A delta starts with the size of the base and of the result, followed by
+/// instructions that either copy a range of the base or insert literal bytes
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> GitResult<Vec<u8>> {
+    let mut delta = delta;
+
+    let base_size = read_size(&mut delta)?;
+    if base_size != base.len() {
+        return Err(invalid("base size mismatch"));
+    }
+    let result_size = read_size(&mut delta)?;
+
+    let mut result = Vec::new();
+
+    while let Some((&instruction, rest)) = delta.split_first() {
+        delta = rest;
+
+        if instruction & 0x80 != 0 {
+            // bits 0-3 tell which offset bytes are present, bits 4-6 which size bytes
+            let mut offset = 0;
+            for shift in 0..4 {
+                if instruction & (1 << shift) != 0 {
+                    offset |= (take_byte(&mut delta)? as usize) << (8 * shift);
+                }
+            }
+
+            let mut size = 0;
+            for shift in 0..3 {
+                if instruction & (1 << (4 + shift)) != 0 {
+                    size |= (take_byte(&mut delta)? as usize) << (8 * shift);
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+
+            let range = base
+                .get(offset..offset + size)
+                .ok_or_else(|| invalid("copy out of bounds"))?;
+            result.extend_from_slice(range);
+        } else if instruction != 0 {
+            let size = instruction as usize;
+            if delta.len() < size {
+                return Err(invalid("insert out of bounds"));
+            }
+            let (data, rest) = delta.split_at(size);
+            result.extend_from_slice(data);
+            delta = rest;
+        } else {
+            return Err(invalid("reserved instruction"));
+        }
+
+        if result.len() > result_size {
+            return Err(invalid("result size mismatch"));
+        }
+    }
+
+    if result.len() != result_size {
+        return Err(invalid("result size mismatch"));
+    }
+
+    Ok(result)
+}
+
+/// Encodes the target as a delta against the base, in the format read by `apply_delta`.
+///
+/// Every aligned block of the base is indexed, and for each position of the
+/// target a matching block is extended as far as possible into a copy.
+/// Anything that cannot be copied is inserted literally
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_size(&mut delta, base.len());
+    write_size(&mut delta, target.len());
+
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for (position, block) in base.chunks_exact(BLOCK_SIZE).enumerate() {
+        blocks.entry(block).or_insert(position * BLOCK_SIZE);
+    }
+
+    let mut pending_insert = Vec::new();
+    let mut position = 0;
+
+    while position < target.len() {
+        let matched = target
+            .get(position..position + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block))
+            .map(|&offset| {
+                let length = base[offset..]
+                    .iter()
+                    .zip(&target[position..])
+                    .take(MAX_COPY_SIZE)
+                    .take_while(|(base_byte, target_byte)| base_byte == target_byte)
+                    .count();
+                (offset, length)
+            });
+
+        match matched {
+            Some((offset, length)) => {
+                write_insert(&mut delta, &pending_insert);
+                pending_insert.clear();
+
+                write_copy(&mut delta, offset, length);
+                position += length;
+            }
+            None => {
+                pending_insert.push(target[position]);
+                position += 1;
+            }
+        }
+    }
+
+    write_insert(&mut delta, &pending_insert);
+
+    delta
+}
+
+fn write_insert(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT_SIZE) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+/// Writes a copy instruction, omitting every offset and size byte that is zero
+fn write_copy(delta: &mut Vec<u8>, offset: usize, size: usize) {
+    let mut instruction = 0x80;
+    let mut arguments = Vec::with_capacity(7);
+
+    for shift in 0..4 {
+        let byte = (offset >> (8 * shift)) as u8;
+        if byte != 0 {
+            instruction |= 1 << shift;
+            arguments.push(byte);
+        }
+    }
+
+    for shift in 0..3 {
+        let byte = (size >> (8 * shift)) as u8;
+        if byte != 0 {
+            instruction |= 1 << (4 + shift);
+            arguments.push(byte);
+        }
+    }
+
+    delta.push(instruction);
+    delta.extend_from_slice(&arguments);
+}
+
+/// Writes a size in the format read by `read_size`
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        delta.push((size as u8 & 0x7f) | 0x80);
+        size >>= 7;
+    }
+    delta.push(size as u8);
+}
+
+/// Reads only the size of the result from the start of a delta
+pub fn delta_result_size(mut delta: &[u8]) -> GitResult<usize> {
+    read_size(&mut delta)?;
+    read_size(&mut delta)
+}
+
+/// Reads a little endian base 128 size, where the highest bit of each byte
+/// tells whether another byte follows
+fn read_size(delta: &mut &[u8]) -> GitResult<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = take_byte(delta)?;
+        if shift >= usize::BITS {
+            return Err(invalid("size too large"));
+        }
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+fn take_byte(delta: &mut &[u8]) -> GitResult<u8> {
+    let (&byte, rest) = delta
+        .split_first()
+        .ok_or_else(|| invalid("unexpected end"))?;
+    *delta = rest;
+    Ok(byte)
+}
+
+fn invalid(reason: &str) -> GitError {
+    GitError::InvalidDelta(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn applies_copy_and_insert_instructions() {
+        let base = b"hello world, this is the base";
+        let delta = [
+            29, // base size
+            23, // result size
+            0b1001_0001,
+            6, // copy offset 6
+            5, // copy size 5
+            2,
+            b'!',
+            b' ',
+            0b1001_0000,
+            16, // copy offset 0, size 16
+        ];
+
+        let result = apply_delta(base, &delta).unwrap();
+
+        assert_eq!(result, b"world! hello world, thi".to_vec());
+        assert_eq!(delta_result_size(&delta).unwrap(), result.len());
+    }
+
+    #[test]
+    pub fn rejects_results_of_the_wrong_size() {
+        let base = b"hello world";
+        // a result size of 2^63 does not make the delta allocate it
+        let mut delta = vec![
+            11, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01,
+        ];
+        delta.extend_from_slice(&[0b1001_0000, 11]);
+        assert!(matches!(
+            apply_delta(base, &delta),
+            Err(GitError::InvalidDelta(_))
+        ));
+
+        let delta = [11, 5, 0b1001_0000, 11];
+        assert!(matches!(
+            apply_delta(base, &delta),
+            Err(GitError::InvalidDelta(_))
+        ));
+    }
+
+    #[test]
+    pub fn created_deltas_rebuild_the_target() {
+        let base: Vec<u8> = (0..2000)
+            .flat_map(|n: u32| n.to_string().into_bytes())
+            .collect();
+        let mut target = base[100..1500].to_vec();
+        target.extend_from_slice(b"some new content");
+        target.extend_from_slice(&base[..700]);
+
+        let delta = create_delta(&base, &target);
+
+        assert!(delta.len() < target.len() / 10);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    object::{Oid, RawOid, OID_HEX_LEN},
+    repository::{GitError, GitResult},
+};
+
+const INDEX_SIGNATURE: &[u8; 4] = b"\xfftOc";
+const INDEX_VERSION: u32 = 2;
+
+/// number of entries in the fanout table, one for each possible first byte of an id
+const FANOUT_LENGTH: usize = 256;
+/// signature, version and fanout table
+const HEADER_SIZE: u64 = 8 + FANOUT_LENGTH as u64 * 4;
+
+/// offsets with this bit set are an index into the table of 64 bit offsets
+const LARGE_OFFSET_FLAG: u32 = 0x80000000;
+
+/// Id, offset in the pack and crc32 of the stored entry of an object
+pub type PackIndexEntry = (Oid, u64, u32);
+
+/// A version 2 pack index, which maps object ids to their offset in the pack.
+///
+/// Only the fanout table is kept in memory, every lookup is a binary search
+/// over the sorted ids on disk
+pub struct PackIndex {
+    path: PathBuf,
+    fanout: [u32; FANOUT_LENGTH],
+}
+
+impl PackIndex {
+    pub fn open<P: AsRef<Path>>(path: P) -> GitResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut reader = BufReader::new(File::open(&path)?);
+
+        let mut signature = [0; 4];
+        reader.read_exact(&mut signature)?;
+        if &signature != INDEX_SIGNATURE {
+            return Err(invalid(&path, "invalid signature"));
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != INDEX_VERSION {
+            return Err(invalid(&path, "unsupported version"));
+        }
+
+        let mut fanout = [0; FANOUT_LENGTH];
+        for count in fanout.iter_mut() {
+            *count = read_u32(&mut reader)?;
+        }
+
+        if fanout.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(invalid(&path, "fanout table is not sorted"));
+        }
+
+        // ids, crc32s, offsets and both checksums, followed by at most one
+        // large offset for every entry but the first
+        let count = fanout[FANOUT_LENGTH - 1] as u64;
+        let min_size = HEADER_SIZE + count * (OID_HEX_LEN as u64 + 8) + 2 * OID_HEX_LEN as u64;
+        let max_size = min_size + count.saturating_sub(1) * 8;
+        let size = reader.get_ref().metadata()?.len();
+        if size < min_size || size > max_size || !(size - min_size).is_multiple_of(8) {
+            return Err(invalid(
+                &path,
+                "file size does not match the number of entries",
+            ));
+        }
+
+        Ok(Self { path, fanout })
+    }
+
+    pub fn len(&self) -> usize {
+        self.fanout[FANOUT_LENGTH - 1] as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the offset of the object in the pack, if present
+    pub fn find(&self, oid: &Oid) -> GitResult<Option<u64>> {
+        let raw_oid = oid.to_raw_bytes();
+        let first_byte = raw_oid[0] as usize;
+
+        // the fanout table holds the number of ids whose first byte is lower or equal
+        let mut low = match first_byte {
+            0 => 0,
+            _ => self.fanout[first_byte - 1] as usize,
+        };
+        let mut high = self.fanout[first_byte] as usize;
+
+        let mut reader = File::open(&self.path)?;
+        let mut candidate = RawOid::default();
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            reader.seek(SeekFrom::Start(self.oid_position(middle)))?;
+            reader.read_exact(&mut candidate)?;
+
+            match candidate.cmp(&raw_oid) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    return self.read_offset(&mut reader, middle).map(Some)
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads every entry as its id, offset in the pack and crc32, sorted by id
+    pub fn entries(&self) -> GitResult<Vec<PackIndexEntry>> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(HEADER_SIZE))?;
+
+        let mut oids = Vec::new();
+        for _ in 0..self.len() {
+            let mut raw_oid = RawOid::default();
+            reader.read_exact(&mut raw_oid)?;
+            oids.push(Oid::from_raw_bytes(raw_oid));
+        }
+
+        let mut crcs = Vec::new();
+        for _ in 0..self.len() {
+            crcs.push(read_u32(&mut reader)?);
+        }
+
+        let mut offsets = Vec::new();
+        for _ in 0..self.len() {
+            offsets.push(read_u32(&mut reader)?);
+        }
+
+        let mut large_offsets = Vec::new();
+        let large_count = offsets
+            .iter()
+            .filter(|&&offset| offset & LARGE_OFFSET_FLAG != 0)
+            .count();
+        for _ in 0..large_count {
+            large_offsets.push(read_u64(&mut reader)?);
+        }
+
+        oids.into_iter()
+            .zip(offsets)
+            .zip(crcs)
+            .map(|((oid, offset), crc)| {
+                let offset = self.resolve_offset(offset, |position| {
+                    large_offsets
+                        .get(position)
+                        .copied()
+                        .ok_or_else(|| invalid(&self.path, "large offset out of bounds"))
+                })?;
+                Ok((oid, offset, crc))
+            })
+            .collect()
+    }
+
+    /// Writes an index for the given entries of id, offset in the pack and crc32
+    pub fn serialize<W: Write>(
+        entries: &[PackIndexEntry],
+        pack_checksum: &RawOid,
+        mut writer: W,
+    ) -> GitResult<()> {
+        let mut entries = entries.to_vec();
+        entries.sort();
+
+        let mut content = Vec::new();
+        content.extend_from_slice(INDEX_SIGNATURE);
+        content.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+
+        let mut fanout = [0u32; FANOUT_LENGTH];
+        for (oid, _, _) in &entries {
+            let first_byte = oid.to_raw_bytes()[0] as usize;
+            for count in &mut fanout[first_byte..] {
+                *count += 1;
+            }
+        }
+        for count in fanout {
+            content.extend_from_slice(&count.to_be_bytes());
+        }
+
+        for (oid, _, _) in &entries {
+            content.extend_from_slice(&oid.to_raw_bytes());
+        }
+
+        for (_, _, crc) in &entries {
+            content.extend_from_slice(&crc.to_be_bytes());
+        }
+
+        let mut large_offsets = Vec::new();
+        for &(_, offset, _) in &entries {
+            let offset = match u32::try_from(offset) {
+                Ok(offset) if offset & LARGE_OFFSET_FLAG == 0 => offset,
+                _ => {
+                    large_offsets.push(offset);
+                    (large_offsets.len() as u32 - 1) | LARGE_OFFSET_FLAG
+                }
+            };
+            content.extend_from_slice(&offset.to_be_bytes());
+        }
+        for offset in large_offsets {
+            content.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        content.extend_from_slice(pack_checksum);
+
+        let checksum = Sha1::digest(&content);
+        content.extend_from_slice(&checksum);
+
+        writer.write_all(&content)?;
+
+        Ok(())
+    }
+
+    /// Reads the checksum of the pack this index belongs to
+    pub fn pack_checksum(&self) -> GitResult<RawOid> {
+        let mut reader = File::open(&self.path)?;
+        reader.seek(SeekFrom::End(-2 * OID_HEX_LEN as i64))?;
+
+        let mut checksum = RawOid::default();
+        reader.read_exact(&mut checksum)?;
+
+        Ok(checksum)
+    }
+
+    /// Checks the trailing checksum of the index against its content
+    pub fn verify_checksum(&self) -> GitResult<()> {
+        let content = fs::read(&self.path)?;
+        let (data, checksum) = content
+            .split_at_checked(content.len().saturating_sub(OID_HEX_LEN))
+            .filter(|(data, _)| !data.is_empty())
+            .ok_or_else(|| invalid(&self.path, "truncated index"))?;
+
+        if Sha1::digest(data).as_slice() != checksum {
+            return Err(invalid(&self.path, "index checksum mismatch"));
+        }
+
+        Ok(())
+    }
+
+    fn oid_position(&self, position: usize) -> u64 {
+        HEADER_SIZE + (position * OID_HEX_LEN) as u64
+    }
+
+    fn read_offset(&self, reader: &mut File, position: usize) -> GitResult<u64> {
+        let count = self.len() as u64;
+
+        // ids are followed by a table of crc32 and then by a table of offsets
+        let offsets_start = HEADER_SIZE + count * OID_HEX_LEN as u64 + count * 4;
+        reader.seek(SeekFrom::Start(offsets_start + position as u64 * 4))?;
+        let offset = read_u32(reader)?;
+
+        self.resolve_offset(offset, |large_position| {
+            let large_offsets_start = offsets_start + count * 4;
+            reader.seek(SeekFrom::Start(
+                large_offsets_start + large_position as u64 * 8,
+            ))?;
+            read_u64(reader)
+        })
+    }
+
+    fn resolve_offset<F>(&self, offset: u32, mut read_large_offset: F) -> GitResult<u64>
+    where
+        F: FnMut(usize) -> GitResult<u64>,
+    {
+        if offset & LARGE_OFFSET_FLAG != 0 {
+            read_large_offset((offset & !LARGE_OFFSET_FLAG) as usize)
+        } else {
+            Ok(offset as u64)
+        }
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> GitResult<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> GitResult<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn invalid(path: &Path, reason: &str) -> GitError {
+    GitError::InvalidPack(format!("{}: {}", path.display(), reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn lookups_find_small_and_large_offsets() {
+        let entries: Vec<PackIndexEntry> = [
+            ("0000000000000000000000000000000000000001", 12, 1),
+            ("8000000000000000000000000000000000000000", 1 << 32, 2),
+            ("ff00000000000000000000000000000000000000", 0x7fff_ffff, 3),
+        ]
+        .into_iter()
+        .map(|(oid, offset, crc)| (Oid::new(oid).unwrap(), offset, crc))
+        .collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.idx");
+        PackIndex::serialize(&entries, &[7; OID_HEX_LEN], File::create(&path).unwrap()).unwrap();
+
+        let index = PackIndex::open(&path).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.entries().unwrap(), entries);
+        for (oid, offset, _) in &entries {
+            assert_eq!(index.find(oid).unwrap(), Some(*offset));
+        }
+        let missing = Oid::new("8000000000000000000000000000000000000001").unwrap();
+        assert_eq!(index.find(&missing).unwrap(), None);
+        assert_eq!(index.pack_checksum().unwrap(), [7; OID_HEX_LEN]);
+        index.verify_checksum().unwrap();
+
+        let mut content = fs::read(&path).unwrap();
+        content[HEADER_SIZE as usize] ^= 1;
+        fs::write(&path, content).unwrap();
+        assert!(index.verify_checksum().is_err());
+
+        // a count that the file is too short to hold is rejected before reading
+        let mut content = fs::read(&path).unwrap();
+        content[HEADER_SIZE as usize - 4..HEADER_SIZE as usize].copy_from_slice(&[0xff; 4]);
+        fs::write(&path, content).unwrap();
+        assert!(matches!(
+            PackIndex::open(&path),
+            Err(GitError::InvalidPack(_))
+        ));
+    }
+}
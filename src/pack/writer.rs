@@ -0,0 +1,260 @@
+use std::io::Write;
+
+use flate2::{write::ZlibEncoder as ZlibWriteEncoder, Compression, Crc};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    object::{ObjectKind, Oid, RawObject, RawOid},
+    repository::GitResult,
+    utils::write_offset_varint,
+};
+
+use super::{delta::create_delta, PackIndexEntry, PACK_SIGNATURE};
+
+const PACK_VERSION: u32 = 2;
+
+/// How hard to look for deltas when writing a pack
+#[derive(Clone, Copy, Debug)]
+pub struct DeltaOptions {
+    /// number of previous objects each one is compared against
+    pub window: usize,
+    /// longest allowed chain of deltas
+    pub depth: usize,
+}
+
+impl Default for DeltaOptions {
+    fn default() -> Self {
+        Self {
+            window: 10,
+            depth: 50,
+        }
+    }
+}
+
+/// An object about to be written, either whole or as a delta against the
+/// object at position `base` of the pack
+struct PackedObject {
+    oid: Oid,
+    object: RawObject,
+    delta: Option<(usize, Vec<u8>)>,
+    depth: usize,
+}
+
+/// Writes a version 2 pack with the given objects, returning the checksum of
+/// the pack and the id, offset and crc32 of each object, as needed by the index.
+///
+/// Objects are sorted by type and decreasing size, so that similar objects
+/// are close together, and each one is compared against the previous ones in
+/// the window. Deltas are only kept when they are at most half the size of
+/// the object, and are always stored after their base, as `OFS_DELTA`
+pub fn write_pack<W: Write>(
+    objects: Vec<(Oid, RawObject)>,
+    options: DeltaOptions,
+    writer: W,
+) -> GitResult<(RawOid, Vec<PackIndexEntry>)> {
+    let mut objects: Vec<PackedObject> = objects
+        .into_iter()
+        .map(|(oid, object)| PackedObject {
+            oid,
+            object,
+            delta: None,
+            depth: 0,
+        })
+        .collect();
+
+    objects.sort_by(|a, b| {
+        kind_order(a.object.kind())
+            .cmp(&kind_order(b.object.kind()))
+            .then(b.object.size().cmp(&a.object.size()))
+            .then(a.oid.cmp(&b.oid))
+    });
+    objects.dedup_by(|a, b| a.oid == b.oid);
+
+    for target in 0..objects.len() {
+        let window_start = target.saturating_sub(options.window);
+        let mut best: Option<(usize, Vec<u8>)> = None;
+
+        for base in window_start..target {
+            let (base_object, target_object) = (&objects[base], &objects[target]);
+            if base_object.object.kind() != target_object.object.kind()
+                || base_object.depth >= options.depth
+            {
+                continue;
+            }
+
+            let delta = create_delta(base_object.object.content(), target_object.object.content());
+            let max_size = best
+                .as_ref()
+                .map(|(_, best_delta)| best_delta.len())
+                .unwrap_or(target_object.object.size() / 2);
+
+            if delta.len() < max_size {
+                best = Some((base, delta));
+            }
+        }
+
+        if let Some((base, delta)) = best {
+            objects[target].depth = objects[base].depth + 1;
+            objects[target].delta = Some((base, delta));
+        }
+    }
+
+    let mut writer = HashingWriter::new(writer);
+
+    writer.write_all(PACK_SIGNATURE)?;
+    writer.write_all(&PACK_VERSION.to_be_bytes())?;
+    writer.write_all(&(objects.len() as u32).to_be_bytes())?;
+
+    let mut entries: Vec<PackIndexEntry> = Vec::with_capacity(objects.len());
+
+    for object in &objects {
+        let offset = writer.written;
+
+        let mut entry = Vec::new();
+        match &object.delta {
+            Some((base, delta)) => {
+                write_entry_header(&mut entry, 6, delta.len());
+                // distance to the base, in the format read by `Pack::read_entry_header`
+                write_offset_varint(&mut entry, offset - entries[*base].1);
+                compress(&mut entry, delta)?;
+            }
+            None => {
+                write_entry_header(
+                    &mut entry,
+                    kind_number(object.object.kind()),
+                    object.object.size(),
+                );
+                compress(&mut entry, object.object.content())?;
+            }
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&entry);
+
+        writer.write_all(&entry)?;
+        entries.push((object.oid.clone(), offset, crc.sum()));
+    }
+
+    let checksum: RawOid = writer.hasher.finalize().into();
+    writer.inner.write_all(&checksum)?;
+
+    Ok((checksum, entries))
+}
+
+/// Commits first, as git does, so that history can be walked without reading
+/// the rest of the pack
+fn kind_order(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Commit => 0,
+        ObjectKind::Tag => 1,
+        ObjectKind::Tree => 2,
+        ObjectKind::Blob => 3,
+    }
+}
+
+fn kind_number(kind: ObjectKind) -> u8 {
+    match kind {
+        ObjectKind::Commit => 1,
+        ObjectKind::Tree => 2,
+        ObjectKind::Blob => 3,
+        ObjectKind::Tag => 4,
+    }
+}
+
+/// Writes the type and size of an entry, in the format read by `Pack::read_entry_header`
+fn write_entry_header(entry: &mut Vec<u8>, kind: u8, size: usize) {
+    let mut byte = (kind << 4) | (size as u8 & 0x0f);
+    let mut size = size >> 4;
+
+    while size != 0 {
+        entry.push(byte | 0x80);
+        byte = size as u8 & 0x7f;
+        size >>= 7;
+    }
+    entry.push(byte);
+}
+
+fn compress(entry: &mut Vec<u8>, data: &[u8]) -> GitResult<()> {
+    let mut encoder = ZlibWriteEncoder::new(entry, Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()?;
+
+    Ok(())
+}
+
+/// Writer that hashes and counts everything written through it
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha1,
+    written: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha1::new(),
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use pretty_assertions_sorted::assert_eq;
+
+    use crate::pack::{Pack, PackIndex};
+
+    use super::*;
+
+    #[test]
+    pub fn written_packs_can_be_read_back() {
+        let base: Vec<u8> = (0..4096).map(|i| (i * 7 % 251) as u8).collect();
+        let mut similar = base.clone();
+        similar[100..110].copy_from_slice(b"0123456789");
+
+        let objects: Vec<(Oid, RawObject)> = [base, similar, b"small".to_vec()]
+            .into_iter()
+            .map(|content| {
+                let object = RawObject::new(ObjectKind::Blob, content);
+                (object.hash(), object)
+            })
+            .collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let pack_path = dir.path().join("test.pack");
+        let index_path = dir.path().join("test.idx");
+
+        let (checksum, entries) = write_pack(
+            objects.clone(),
+            DeltaOptions::default(),
+            File::create(&pack_path).unwrap(),
+        )
+        .unwrap();
+        PackIndex::serialize(&entries, &checksum, File::create(&index_path).unwrap()).unwrap();
+
+        let pack = Pack::open(&index_path).unwrap();
+        let pack_size = fs::metadata(&pack_path).unwrap().len();
+        for (oid, object) in &objects {
+            assert_eq!(pack.read_object(oid).unwrap().as_ref(), Some(object));
+        }
+
+        // the second blob is stored as a small delta against the first one
+        assert!(pack_size < 4096 + 1024);
+    }
+}
@@ -1,13 +1,36 @@
 use std::{
-    env,
-    fs::{create_dir_all, File},
-    io::{self, Seek},
-    path::{Path, PathBuf},
+    collections::HashSet,
+    env, fmt,
+    fs::{self, create_dir_all, File},
+    io::{self, BufReader, BufWriter},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use thiserror::Error;
 
-use crate::object::{Blob, Oid};
+use crate::{
+    config::Config,
+    index::{verify_path, CacheTree, Index, IndexEntry, DEFAULT_INDEX_VERSION},
+    object::{Object, ObjectKind, Oid, RawObject, Signature, Tree, TreeEntry},
+    pack::{write_pack, DeltaOptions, Pack, PackIndex},
+    utils::write_locked,
+};
+
+mod discover;
+mod handle;
+mod identity;
+mod refs;
+
+pub use discover::{is_git_dir, write_gitfile, RepositoryLocation};
+pub use handle::{InitOptions, Repository};
+pub use refs::{
+    delete_reference, is_valid_reference_name, list_references, list_references_and_errors,
+    pack_references, read_packed_references, read_reference, read_reference_target,
+    read_symbolic_reference, reference_exists, resolve_head, resolve_reference_name,
+    resolve_revision, update_reference, write_reference, write_symbolic_reference, ReferenceTarget,
+};
 
 #[derive(Error, Debug)]
 pub enum GitError {
@@ -16,27 +39,221 @@ pub enum GitError {
 
     #[error("invalid object id")]
     InvalidOid,
+
+    #[error("object not found: {0}")]
+    ObjectNotFound(Oid),
+
+    #[error("invalid object type: {0}")]
+    InvalidObjectKind(String),
+
+    #[error("expected {expected} object, found {found}")]
+    UnexpectedObjectKind {
+        expected: ObjectKind,
+        found: ObjectKind,
+    },
+
+    #[error("invalid object header")]
+    InvalidObjectHeader,
+
+    #[error("malformed {kind} object: {reason}")]
+    MalformedObject { kind: ObjectKind, reason: String },
+
+    #[error("invalid signature: {0}")]
+    InvalidSignature(String),
+
+    #[error("invalid date: {0}")]
+    InvalidDate(String),
+
+    #[error("invalid reference name: {0}")]
+    InvalidReferenceName(String),
+
+    #[error("reference not found: {0}")]
+    ReferenceNotFound(String),
+
+    #[error("reference already exists: {0}")]
+    ReferenceAlreadyExists(String),
+
+    #[error("symbolic reference loop: {0}")]
+    SymbolicReferenceLoop(String),
+
+    #[error("refusing to point a symbolic reference outside of refs/: {0}")]
+    InvalidSymbolicReference(String),
+
+    #[error("ref {0} is not a symbolic ref")]
+    NotASymbolicReference(String),
+
+    #[error("deleting '{0}' is not allowed")]
+    ProtectedReference(String),
+
+    #[error("invalid revision: {0}")]
+    InvalidRevision(String),
+
+    #[error("invalid tree entry name: {0}")]
+    InvalidTreeEntryName(String),
+
+    #[error("invalid file mode: {0:o}")]
+    InvalidFileMode(u32),
+
+    #[error("duplicate tree entry: {0}")]
+    DuplicateTreeEntry(String),
+
+    #[error("path has unmerged entries: {0}")]
+    UnmergedEntry(String),
+
+    #[error("prefix not found: {0}")]
+    PrefixNotFound(String),
+
+    #[error("{0}: cannot add to the index - missing --add option?")]
+    MissingAddOption(String),
+
+    #[error("{0}: does not exist and --remove not passed")]
+    MissingRemoveOption(String),
+
+    #[error("{0}: is a directory - add files inside instead")]
+    PathIsDirectory(String),
+
+    #[error("invalid path '{0}'")]
+    InvalidPath(String),
+
+    #[error("{0}: is outside repository")]
+    PathOutsideRepository(String),
+
+    #[error("{0}: is inside the git directory")]
+    PathInGitDir(String),
+
+    #[error("path is not in the index: {0}")]
+    PathNotInIndex(String),
+
+    #[error("no resolved conflict to restore: {0}")]
+    NothingToUnresolve(String),
+
+    #[error("invalid cache info: {0}")]
+    InvalidCacheInfo(String),
+
+    #[error("invalid index info: {0}")]
+    InvalidIndexInfo(String),
+
+    #[error("invalid pack: {0}")]
+    InvalidPack(String),
+
+    #[error("invalid delta: {0}")]
+    InvalidDelta(String),
+
+    #[error("object size mismatch: header declares {expected} bytes, found {found}")]
+    ObjectSizeMismatch { expected: usize, found: usize },
+
+    #[error("index file has an invalid signature")]
+    InvalidIndexSignature,
+
+    #[error("unsupported index version: {0}")]
+    UnsupportedIndexVersion(u32),
+
+    #[error("invalid index version: {0}")]
+    InvalidIndexVersion(String),
+
+    #[error("index file checksum mismatch")]
+    IndexChecksumMismatch,
+
+    #[error("corrupt index: {0}")]
+    CorruptIndex(String),
+
+    #[error("unsupported index extension: {0}")]
+    UnsupportedIndexExtension(String),
+
+    #[error("not a git repository (or any of the parent directories): {0}")]
+    NotARepository(String),
+
+    #[error("this operation must be run in a work tree")]
+    NoWorkTree,
+
+    #[error("invalid gitfile format: {0}")]
+    InvalidGitFile(String),
+
+    #[error("bad config line {line} in file {path}")]
+    InvalidConfig { line: usize, path: String },
+
+    #[error("bad {kind} config value '{value}' for '{key}'")]
+    InvalidConfigValue {
+        kind: &'static str,
+        value: String,
+        key: String,
+    },
+
+    #[error("key does not contain a section: {0}")]
+    ConfigKeyWithoutSection(String),
+
+    #[error("invalid key: {0}")]
+    InvalidConfigKey(String),
+
+    #[error("{0} has multiple values")]
+    MultipleConfigValues(String),
+
+    #[error("invalid pattern: {0}")]
+    InvalidRegex(String),
+
+    #[error("{0} identity unknown, set user.name and user.email with `grit config`")]
+    UnknownIdentity(&'static str),
 }
 
 pub type GitResult<T> = Result<T, GitError>;
 
-const GIT_DIR: &str = ".grit";
+pub const GIT_DIR: &str = ".grit";
 const GIT_DIR_ENV: &str = "GRIT_DIR";
+const INDEX_VERSION_ENV: &str = "GIT_INDEX_VERSION";
 
 const OBJECT_PREFIX_LENGTH: usize = 2;
 
+/// default number of loose objects above which `gc --auto` repacks
+pub const GC_AUTO_THRESHOLD: usize = 6700;
+/// number of packs above which `gc --auto` consolidates them
+pub const GC_AUTO_PACK_LIMIT: usize = 50;
+/// loose objects starting with this id are counted to estimate the total
+const GC_AUTO_SAMPLE_ID: &str = "1700000000000000000000000000000000000000";
+
 pub const DEFAULT_BRANCH: &str = "master";
-pub const DEFAULT_CONTENT: &str = "\
-            [core]\n\
-            \trepositoryformatversion = 0\n\
-            \tfilemode = true\n\
-            \tbare = false\n\
-            \tlogallrefupdates\n";
 
-pub fn get_git_dir() -> PathBuf {
-    let git_dir = env::var(GIT_DIR_ENV);
-    let git_dir = git_dir.as_deref().unwrap_or(GIT_DIR);
-    PathBuf::from(git_dir)
+/// Git dir `init` creates for a repository in `directory`: `$GRIT_DIR` when
+/// set, otherwise the directory itself if bare, or its `.grit`
+pub fn get_init_git_dir(directory: &Path, bare: bool) -> PathBuf {
+    match env::var_os(GIT_DIR_ENV) {
+        Some(git_dir) => PathBuf::from(git_dir),
+        None if bare => directory.to_path_buf(),
+        None => directory.join(GIT_DIR),
+    }
+}
+
+/// Converts a path given relative to the current directory, or absolute, to
+/// the name of its index entry, relative to the root of the working tree. As
+/// with git, `..` is resolved lexically rather than by following symlinks
+pub fn normalize_path(git_dir: &Path, work_tree: &Path, path: &Path) -> GitResult<String> {
+    let root = work_tree.canonicalize()?;
+    let git_dir = git_dir.canonicalize()?;
+
+    let mut absolute = PathBuf::new();
+    for component in env::current_dir()?.join(path).components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            component => absolute.push(component),
+        }
+    }
+
+    let display = path.display().to_string();
+    if absolute.starts_with(&git_dir) {
+        return Err(GitError::PathInGitDir(display));
+    }
+    let name = absolute
+        .strip_prefix(&root)
+        .map_err(|_| GitError::PathOutsideRepository(display.clone()))?
+        .to_str()
+        .ok_or(GitError::InvalidPath(display))?
+        .to_string();
+
+    verify_path(&name)?;
+
+    Ok(name)
 }
 
 pub fn get_object_path(git_dir: &Path, oid: &Oid) -> PathBuf {
@@ -47,12 +264,18 @@ pub fn get_object_path(git_dir: &Path, oid: &Oid) -> PathBuf {
     ))
 }
 
-pub fn get_reference_path(git_dir: &Path, name: &str) -> PathBuf {
-    git_dir.join(get_reference_relative_path(name))
-}
+/// Removes `dir` and its parents as long as they are empty, stopping at `root`
+fn remove_empty_parents(dir: Option<&Path>, root: &Path) -> GitResult<()> {
+    let mut dir = dir;
+    while let Some(current) = dir.filter(|current| *current != root && current.starts_with(root)) {
+        if fs::read_dir(current)?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(current)?;
+        dir = current.parent();
+    }
 
-pub fn get_reference_relative_path(name: &str) -> PathBuf {
-    PathBuf::from("refs/heads").join(name)
+    Ok(())
 }
 
 pub fn create_object_path(git_dir: &Path, oid: &Oid) -> GitResult<PathBuf> {
@@ -63,22 +286,790 @@ pub fn create_object_path(git_dir: &Path, oid: &Oid) -> GitResult<PathBuf> {
     Ok(object_path)
 }
 
-pub fn blob(path: &Path) -> GitResult<Oid> {
-    let git_dir = get_git_dir();
+/// Stores the object unless it already exists, returning the id of the exact
+/// bytes that were written
+pub fn write_object(git_dir: &Path, packs: &PackCache, object: &RawObject) -> GitResult<Oid> {
+    let oid = object.hash();
+
+    if !object_exists(git_dir, packs, &oid)? {
+        let object_path = create_object_path(git_dir, &oid)?;
+        let object_file = File::create(object_path)?;
+
+        object.serialize(object_file)?;
+    }
+
+    Ok(oid)
+}
+
+pub fn get_pack_dir(git_dir: &Path) -> PathBuf {
+    git_dir.join("objects/pack")
+}
+
+/// Opens every pack in the repository, sorted by name
+pub fn get_packs(git_dir: &Path) -> GitResult<Vec<Pack>> {
+    list_pack_indexes(git_dir)?
+        .into_iter()
+        .map(Pack::open)
+        .collect()
+}
 
-    let mut file = File::open(path)?;
-    let length = file.metadata()?.len() as usize;
+/// Lists the paths of the pack indexes, sorted, without opening them
+pub fn list_pack_indexes(git_dir: &Path) -> GitResult<Vec<PathBuf>> {
+    let pack_dir = get_pack_dir(git_dir);
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut index_paths = Vec::new();
+    for entry in fs::read_dir(pack_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "idx") {
+            index_paths.push(path);
+        }
+    }
+    index_paths.sort();
+
+    Ok(index_paths)
+}
+
+/// The packs of a repository, opened once and shared by object lookups
+/// rather than listed and opened again for each of them
+#[derive(Default)]
+pub struct PackCache {
+    packs: Mutex<Option<CachedPacks>>,
+}
+
+impl fmt::Debug for PackCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PackCache").finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone)]
+struct CachedPacks {
+    /// of the pack directory, which changes when packs are added or removed
+    modified: Option<SystemTime>,
+    packs: Arc<Vec<Pack>>,
+}
 
-    let blob_id = Blob::hash(&mut file, length)?;
+impl PackCache {
+    /// Returns the first result of `find` over the packs. Packs written or
+    /// removed since they were opened are only noticed when nothing is found
+    /// or reading fails, in which case they are reloaded if the pack
+    /// directory has changed
+    fn find<T>(
+        &self,
+        git_dir: &Path,
+        find: impl Fn(&Pack) -> GitResult<Option<T>>,
+    ) -> GitResult<Option<T>> {
+        let search = |packs: &[Pack]| -> GitResult<Option<T>> {
+            for pack in packs {
+                if let Some(found) = find(pack)? {
+                    return Ok(Some(found));
+                }
+            }
+            Ok(None)
+        };
 
-    let blob_path = get_object_path(&git_dir, &blob_id);
-    if let Some(base) = blob_path.parent() {
+        let cached = self.packs.lock().expect("lock is never poisoned").clone();
+        if let Some(cached) = cached {
+            match search(&cached.packs) {
+                Ok(Some(found)) => return Ok(Some(found)),
+                // reloading the same packs would not find anything more
+                result if cached.modified == pack_dir_modified(git_dir) => return result,
+                _ => (),
+            }
+        }
+
+        let modified = pack_dir_modified(git_dir);
+        let packs = Arc::new(get_packs(git_dir)?);
+        *self.packs.lock().expect("lock is never poisoned") = Some(CachedPacks {
+            modified,
+            packs: Arc::clone(&packs),
+        });
+
+        search(&packs)
+    }
+}
+
+fn pack_dir_modified(git_dir: &Path) -> Option<SystemTime> {
+    fs::metadata(get_pack_dir(git_dir))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Writes the given objects into a new pack named `<base_name>-<checksum>.pack`,
+/// along with its index, returning the checksum
+pub fn write_pack_files(
+    git_dir: &Path,
+    packs: &PackCache,
+    base_name: &Path,
+    oids: &[Oid],
+    options: DeltaOptions,
+) -> GitResult<Oid> {
+    let objects = oids
+        .iter()
+        .map(|oid| Ok((oid.clone(), read_raw_object(git_dir, packs, oid)?)))
+        .collect::<GitResult<Vec<_>>>()?;
+
+    if let Some(base) = base_name
+        .parent()
+        .filter(|base| !base.as_os_str().is_empty())
+    {
         create_dir_all(base)?;
+    }
+
+    // the final name depends on the checksum, which is only known once written
+    let temporary_path = PathBuf::from(format!("{}.tmp.pack", base_name.display()));
+    let mut pack_file = BufWriter::new(File::create(&temporary_path)?);
+    let (checksum, entries) = write_pack(objects, options, &mut pack_file)?;
+    pack_file.into_inner().map_err(|error| error.into_error())?;
+
+    let checksum = Oid::from_raw_bytes(checksum);
+    let pack_path = PathBuf::from(format!("{}-{checksum}.pack", base_name.display()));
+    let index_path = pack_path.with_extension("idx");
+
+    // packs are found through their index, so it is only put in place once
+    // the pack it describes is
+    fs::rename(temporary_path, pack_path)?;
+
+    // named so that it is not listed as an index while written
+    let temporary_index_path = PathBuf::from(format!("{}.tmp", index_path.display()));
+    let mut index_file = BufWriter::new(File::create(&temporary_index_path)?);
+    PackIndex::serialize(&entries, &checksum.to_raw_bytes(), &mut index_file)?;
+    index_file
+        .into_inner()
+        .map_err(|error| error.into_error())?;
+
+    fs::rename(temporary_index_path, index_path)?;
+
+    Ok(checksum)
+}
+
+/// Lists every object reachable from `include` but not from `exclude`,
+/// following parents of commits, targets of tags and entries of trees.
+/// Submodule commits are not followed, as they live in another repository
+pub fn reachable_objects(
+    git_dir: &Path,
+    packs: &PackCache,
+    include: &[Oid],
+    exclude: &[Oid],
+) -> GitResult<Vec<Oid>> {
+    let mut excluded = HashSet::new();
+    walk_objects(git_dir, packs, exclude, &mut excluded, |_| ())?;
+
+    let mut reachable = Vec::new();
+    walk_objects(git_dir, packs, include, &mut excluded, |oid| {
+        reachable.push(oid.clone())
+    })?;
+
+    Ok(reachable)
+}
+
+/// Visits every object reachable from `start` that is not in `seen`, adding it there
+fn walk_objects<F: FnMut(&Oid)>(
+    git_dir: &Path,
+    packs: &PackCache,
+    start: &[Oid],
+    seen: &mut HashSet<Oid>,
+    mut visit: F,
+) -> GitResult<()> {
+    let mut pending: Vec<Oid> = start.iter().rev().cloned().collect();
+
+    while let Some(oid) = pending.pop() {
+        if !seen.insert(oid.clone()) {
+            continue;
+        }
+        visit(&oid);
+
+        match read_object(git_dir, packs, &oid)? {
+            Object::Commit(commit) => {
+                pending.extend(commit.parents().iter().rev().cloned());
+                pending.push(commit.tree_id().clone());
+            }
+            Object::Tree(tree) => {
+                for entry in tree.entries().iter().rev() {
+                    match entry.kind() {
+                        ObjectKind::Tree => pending.push(entry.oid().clone()),
+                        // blobs have nothing to follow, so they are not even read
+                        ObjectKind::Blob if seen.insert(entry.oid().clone()) => visit(entry.oid()),
+                        _ => (),
+                    }
+                }
+            }
+            Object::Tag(tag) => pending.push(tag.object_id().clone()),
+            Object::Blob(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Options of `repack`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RepackOptions {
+    /// pack every reachable object, instead of only those that are loose
+    pub all: bool,
+    /// remove packs and loose objects made redundant by the new pack
+    pub delete: bool,
+    /// when deleting packs, keep their unreachable objects as loose objects
+    /// with the age of the pack, so that `prune` can expire them later
+    pub keep_unreachable: bool,
+    /// with `keep_unreachable`, leave out the objects of packs modified at or
+    /// before this time, which `prune` would remove right away
+    pub unreachable_expire: Option<i64>,
+    pub delta: DeltaOptions,
+}
+
+/// Packs the reachable objects, returning the checksum of the new pack if
+/// there was anything to pack
+pub fn repack(git_dir: &Path, packs: &PackCache, options: RepackOptions) -> GitResult<Option<Oid>> {
+    let old_packs = get_packs(git_dir)?;
+
+    let roots = list_root_objects(git_dir, packs)?;
+    let reachable = reachable_objects(git_dir, packs, &roots, &[])?;
+
+    let mut oids = Vec::new();
+    for oid in &reachable {
+        let packed = old_packs
+            .iter()
+            .map(|pack| pack.contains(oid))
+            .collect::<GitResult<Vec<_>>>()?
+            .contains(&true);
+        if options.all || !packed {
+            oids.push(oid.clone());
+        }
+    }
+
+    let checksum = match oids.is_empty() {
+        true => None,
+        false => Some(write_pack_files(
+            git_dir,
+            packs,
+            &get_pack_dir(git_dir).join("pack"),
+            &oids,
+            options.delta,
+        )?),
+    };
+
+    if !options.delete {
+        return Ok(checksum);
+    }
+
+    if options.all {
+        let reachable: HashSet<Oid> = reachable.into_iter().collect();
+
+        for pack in old_packs {
+            let new_pack = checksum
+                .as_ref()
+                .is_some_and(|checksum| pack.path().ends_with(format!("pack-{checksum}.pack")));
+            if new_pack || pack.path().with_extension("keep").exists() {
+                continue;
+            }
+
+            let pack_time = fs::metadata(pack.path())?.modified()?;
+            let expired = options.unreachable_expire.is_some_and(|expire| {
+                pack_time
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |duration| duration.as_secs() as i64)
+                    <= expire
+            });
+            if options.keep_unreachable && !expired {
+                for (oid, offset, _) in pack.index().entries()? {
+                    let object_path = get_object_path(git_dir, &oid);
+                    if !reachable.contains(&oid) && !object_path.exists() {
+                        let object = pack.read_at(offset)?;
+                        let object_file = File::create(create_object_path(git_dir, &oid)?)?;
+                        object.serialize(&object_file)?;
+                        object_file.set_modified(pack_time)?;
+                    }
+                }
+            }
+
+            for extension in ["pack", "idx", "rev", "bitmap"] {
+                let path = pack.path().with_extension(extension);
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+    }
+
+    prune_packed(git_dir, false)?;
+
+    Ok(checksum)
+}
+
+/// Removes loose objects that are also stored in a pack, returning their ids
+pub fn prune_packed(git_dir: &Path, dry_run: bool) -> GitResult<Vec<Oid>> {
+    let packs = get_packs(git_dir)?;
+
+    let mut pruned = Vec::new();
+    for oid in list_loose_objects(git_dir)? {
+        for pack in &packs {
+            if pack.contains(&oid)? {
+                if !dry_run {
+                    remove_loose_object(git_dir, &oid)?;
+                }
+                pruned.push(oid);
+                break;
+            }
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Removes unreachable loose objects last modified no later than `expire`, in
+/// seconds since epoch, returning their ids
+pub fn prune(git_dir: &Path, packs: &PackCache, expire: i64, dry_run: bool) -> GitResult<Vec<Oid>> {
+    let roots = list_root_objects(git_dir, packs)?;
+    let reachable: HashSet<Oid> = reachable_objects(git_dir, packs, &roots, &[])?
+        .into_iter()
+        .collect();
+
+    let mut pruned = Vec::new();
+    for oid in list_loose_objects(git_dir)? {
+        if reachable.contains(&oid) {
+            continue;
+        }
+
+        let modified = fs::metadata(get_object_path(git_dir, &oid))?.modified()?;
+        let modified = modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+
+        if modified <= expire {
+            if !dry_run {
+                remove_loose_object(git_dir, &oid)?;
+            }
+            pruned.push(oid);
+        }
+    }
+
+    Ok(pruned)
+}
+
+fn remove_loose_object(git_dir: &Path, oid: &Oid) -> GitResult<()> {
+    let object_path = get_object_path(git_dir, oid);
+    fs::remove_file(&object_path)?;
+    remove_empty_parents(object_path.parent(), &git_dir.join("objects"))
+}
+
+/// Lists every object stored in its own file
+pub fn list_loose_objects(git_dir: &Path) -> GitResult<Vec<Oid>> {
+    let mut oids = Vec::new();
+
+    for entry in fs::read_dir(git_dir.join("objects"))? {
+        let entry = entry?;
+        let prefix = entry.file_name();
+        let Some(prefix) = prefix.to_str().filter(|prefix| is_loose_object_dir(prefix)) else {
+            continue;
+        };
+
+        for object in fs::read_dir(entry.path())? {
+            let suffix = object?.file_name();
+            if let Some(oid) = suffix
+                .to_str()
+                .and_then(|suffix| Oid::new(format!("{prefix}{suffix}")).ok())
+            {
+                oids.push(oid);
+            }
+        }
+    }
+    oids.sort();
+
+    Ok(oids)
+}
+
+fn is_loose_object_dir(name: &str) -> bool {
+    name.len() == OBJECT_PREFIX_LENGTH
+        && name
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Checks whether `gc --auto` has anything to do: there are more than about
+/// `threshold` loose objects, or more than `GC_AUTO_PACK_LIMIT` packs. A zero
+/// threshold disables it
+pub fn needs_gc(git_dir: &Path, threshold: usize) -> GitResult<bool> {
+    if threshold == 0 {
+        return Ok(false);
+    }
+
+    // as ids are uniformly distributed, a single directory is a good sample
+    let sample_dir = get_object_path(git_dir, &Oid::new(GC_AUTO_SAMPLE_ID)?);
+    let sample_count = match sample_dir.parent().map(fs::read_dir) {
+        Some(Ok(entries)) => entries.count(),
+        _ => 0,
+    };
+
+    Ok(sample_count > threshold.div_ceil(256) || get_packs(git_dir)?.len() > GC_AUTO_PACK_LIMIT)
+}
+
+/// Lists the objects housekeeping must keep, along with everything reachable
+/// from them: `HEAD`, every reference, the index and the reflogs
+pub fn list_root_objects(git_dir: &Path, packs: &PackCache) -> GitResult<Vec<Oid>> {
+    let mut roots = Vec::new();
+
+    match resolve_head(git_dir) {
+        Ok(head) => roots.push(head),
+        // there is nothing to keep in a branch without commits
+        Err(GitError::ReferenceNotFound(_)) => (),
+        Err(error) => return Err(error),
+    }
+
+    roots.extend(
+        list_references(git_dir, "refs")?
+            .into_iter()
+            .map(|(_, oid)| oid),
+    );
+    roots.extend(list_unreferenced_roots(git_dir, |oid| {
+        object_exists(git_dir, packs, oid)
+    })?);
+
+    Ok(roots)
+}
+
+/// Lists the roots that do not come from references: the index and the
+/// reflogs, whose entries are only kept if `keep` accepts their object, since
+/// they may name objects that were pruned long ago
+pub fn list_unreferenced_roots(
+    git_dir: &Path,
+    mut keep: impl FnMut(&Oid) -> GitResult<bool>,
+) -> GitResult<Vec<Oid>> {
+    let mut roots = Vec::new();
+
+    roots.extend(
+        read_index(git_dir, DEFAULT_INDEX_VERSION)?
+            .entries()
+            .iter()
+            // submodule commits live in another repository
+            .filter(|entry| entry.mode() != 0o160000)
+            .map(|entry| entry.oid().clone()),
+    );
+
+    for (_, entries) in read_reflogs(git_dir)? {
+        for line in entries {
+            for oid in reflog_ids(&line) {
+                if keep(&oid)? {
+                    roots.push(oid);
+                }
+            }
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Reads every reflog under `logs/`, keyed by its path
+fn read_reflogs(git_dir: &Path) -> GitResult<Vec<(PathBuf, Vec<String>)>> {
+    fn walk(path: &Path, reflogs: &mut Vec<(PathBuf, Vec<String>)>) -> GitResult<()> {
+        if path.is_dir() {
+            for entry in fs::read_dir(path)? {
+                walk(&entry?.path(), reflogs)?;
+            }
+        } else if path.is_file() {
+            let content = fs::read_to_string(path)?;
+            reflogs.push((
+                path.to_path_buf(),
+                content.lines().map(str::to_string).collect(),
+            ));
+        }
+        Ok(())
+    }
+
+    let mut reflogs = Vec::new();
+    walk(&git_dir.join("logs"), &mut reflogs)?;
+
+    Ok(reflogs)
+}
+
+/// Old and new ids of a reflog entry, `<old> <new> <signature>\t<message>`,
+/// skipping the null id of created and deleted references
+fn reflog_ids(line: &str) -> Vec<Oid> {
+    line.split(' ')
+        .take(2)
+        .filter_map(|id| Oid::new(id).ok())
+        .filter(|oid| oid.bytes().any(|byte| byte != b'0'))
+        .collect()
+}
+
+/// Removes reflog entries older than `expire`, in seconds since epoch
+pub fn expire_reflogs(git_dir: &Path, expire: i64) -> GitResult<()> {
+    for (path, entries) in read_reflogs(git_dir)? {
+        let count = entries.len();
+        let kept: Vec<String> = entries
+            .into_iter()
+            .filter(|line| {
+                let signature = line
+                    .splitn(3, ' ')
+                    .nth(2)
+                    .and_then(|rest| rest.split('\t').next())
+                    .and_then(|signature| signature.parse::<Signature>().ok());
+                // entries that cannot be parsed are kept, as their age is unknown
+                signature.is_none_or(|signature| signature.time() >= expire)
+            })
+            .map(|line| line + "\n")
+            .collect();
+
+        // logs are only rewritten when needed, since writers append to them
+        if kept.len() != count {
+            write_locked(&path, kept.concat())?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn object_exists(git_dir: &Path, packs: &PackCache, oid: &Oid) -> GitResult<bool> {
+    if get_object_path(git_dir, oid).is_file() {
+        return Ok(true);
+    }
+
+    Ok(packs
+        .find(git_dir, |pack| Ok(pack.contains(oid)?.then_some(())))?
+        .is_some())
+}
+
+/// Reads an object, looking first for a loose copy and then in every pack
+pub fn read_raw_object(git_dir: &Path, packs: &PackCache, oid: &Oid) -> GitResult<RawObject> {
+    let object_path = get_object_path(git_dir, oid);
+    if object_path.is_file() {
+        let object_file = File::open(object_path)?;
+        return RawObject::deserialize(BufReader::new(object_file));
+    }
+
+    packs
+        .find(git_dir, |pack| pack.read_object(oid))?
+        .ok_or_else(|| GitError::ObjectNotFound(oid.clone()))
+}
+
+/// Reads only the header of an object, which is enough to know its type and size
+pub fn read_object_header(
+    git_dir: &Path,
+    packs: &PackCache,
+    oid: &Oid,
+) -> GitResult<(ObjectKind, usize)> {
+    let object_path = get_object_path(git_dir, oid);
+    if object_path.is_file() {
+        let object_file = File::open(object_path)?;
+        return RawObject::deserialize_kind_and_size(BufReader::new(object_file));
+    }
+
+    packs
+        .find(git_dir, |pack| pack.read_object_header(oid))?
+        .ok_or_else(|| GitError::ObjectNotFound(oid.clone()))
+}
+
+pub fn read_object(git_dir: &Path, packs: &PackCache, oid: &Oid) -> GitResult<Object> {
+    Object::from_raw(read_raw_object(git_dir, packs, oid)?)
+}
+
+/// Reads the tree an object refers to, peeling tags and commits
+pub fn read_tree_ish(git_dir: &Path, packs: &PackCache, oid: &Oid) -> GitResult<Tree> {
+    let mut oid = oid.clone();
+
+    loop {
+        oid = match read_object(git_dir, packs, &oid)? {
+            Object::Tree(tree) => return Ok(tree),
+            Object::Commit(commit) => commit.tree_id().clone(),
+            Object::Tag(tag) => tag.object_id().clone(),
+            Object::Blob(_) => {
+                return Err(GitError::UnexpectedObjectKind {
+                    expected: ObjectKind::Tree,
+                    found: ObjectKind::Blob,
+                })
+            }
+        };
+    }
+}
+
+/// Reads the index, which is empty if it was never written
+pub fn read_index(git_dir: &Path, default_version: u32) -> GitResult<Index> {
+    match Index::deserialize_from_path(git_dir.join("index")) {
+        Err(GitError::IO(error)) if error.kind() == io::ErrorKind::NotFound => {
+            let mut index = Index::default();
+            index.set_version(default_version)?;
+            Ok(index)
+        }
+        result => result,
+    }
+}
+
+/// Writes the index, first smudging entries which would otherwise look clean
+/// despite their file having changed
+pub fn write_index(git_dir: &Path, work_tree: Option<&Path>, index: &mut Index) -> GitResult<()> {
+    if let Some(work_tree) = work_tree {
+        index.smudge_racy_entries(work_tree)?;
+    }
+    index.serialize_to_path(git_dir.join("index"))
+}
+
+/// Version of newly created indexes, from `GIT_INDEX_VERSION` or else
+/// `index.version`. Existing indexes keep the version they were read with
+pub fn default_index_version(config: &Config) -> GitResult<u32> {
+    match env::var(INDEX_VERSION_ENV) {
+        Ok(version) => version
+            .parse()
+            .map_err(|_| GitError::InvalidIndexVersion(version)),
+        Err(_) => match config.get_int("index.version")? {
+            Some(version) => u32::try_from(version)
+                .map_err(|_| GitError::InvalidIndexVersion(version.to_string())),
+            None => Ok(DEFAULT_INDEX_VERSION),
+        },
+    }
+}
+
+/// Lists every file in the working tree, relative to its root and sorted as
+/// the index, skipping the git dir and any nested `.git` directory
+pub fn list_working_tree_files(git_dir: &Path, work_tree: &Path) -> GitResult<Vec<String>> {
+    fn walk(
+        directory: &Path,
+        base: &str,
+        git_dir: &Path,
+        files: &mut Vec<String>,
+    ) -> GitResult<()> {
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if name == ".git" || path.canonicalize()? == git_dir {
+                    continue;
+                }
+                walk(&path, &format!("{base}{name}/"), git_dir, files)?;
+            } else {
+                files.push(format!("{base}{name}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(work_tree, "", &git_dir.canonicalize()?, &mut files)?;
+    files.sort();
+
+    Ok(files)
+}
+
+/// Writes one tree object per directory in the index, returning the id of the
+/// tree at `prefix`, or of the root tree if the prefix is empty
+pub fn write_tree(
+    git_dir: &Path,
+    packs: &PackCache,
+    index: &mut Index,
+    prefix: &str,
+) -> GitResult<Oid> {
+    if let Some(entry) = index.entries().iter().find(|entry| entry.stage() != 0) {
+        return Err(GitError::UnmergedEntry(entry.name().to_string()));
+    }
+
+    let prefix = prefix.trim_end_matches('/');
+    let directory = if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{prefix}/")
     };
-    let blob_file = File::create(blob_path)?;
 
-    file.seek(io::SeekFrom::Start(0))?;
-    Blob::serialize(file, blob_file, length)?;
+    let entries: Vec<&IndexEntry> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.name().starts_with(&directory))
+        .collect();
+
+    if entries.is_empty() && !directory.is_empty() {
+        return Err(GitError::PrefixNotFound(prefix.to_string()));
+    }
+
+    let cached = index
+        .cache_tree()
+        .and_then(|cache_tree| cache_tree.find(prefix));
+    let (tree_id, cache_tree) =
+        write_subtree(git_dir, packs, &entries, directory.len(), "", cached)?;
+
+    // the cache tree of a prefix is already part of the root one
+    if directory.is_empty() {
+        index.set_cache_tree(cache_tree);
+    }
+
+    Ok(tree_id)
+}
+
+/// Writes the tree for entries sharing the first `depth` bytes of their name,
+/// recursing into each subdirectory. As the index is sorted, all entries of a
+/// subdirectory are contiguous. Directories whose cached tree is still valid
+/// are not rehashed
+fn write_subtree(
+    git_dir: &Path,
+    packs: &PackCache,
+    entries: &[&IndexEntry],
+    depth: usize,
+    name: &str,
+    cached: Option<&CacheTree>,
+) -> GitResult<(Oid, CacheTree)> {
+    if let Some(cached) = cached {
+        if let Some(oid) = cached.oid() {
+            if cached.entry_count() == entries.len() && object_exists(git_dir, packs, oid)? {
+                return Ok((oid.clone(), cached.clone()));
+            }
+        }
+    }
+
+    let mut tree_entries = Vec::new();
+    let mut children = Vec::new();
+
+    let mut position = 0;
+    while position < entries.len() {
+        let name = &entries[position].name()[depth..];
+
+        match name.split_once('/') {
+            Some((directory, _)) => {
+                let directory_prefix = format!("{directory}/");
+                let length = entries[position..]
+                    .iter()
+                    .take_while(|entry| entry.name()[depth..].starts_with(&directory_prefix))
+                    .count();
+
+                let subtree_entries = &entries[position..position + length];
+                let (subtree_id, subtree) = write_subtree(
+                    git_dir,
+                    packs,
+                    subtree_entries,
+                    depth + directory_prefix.len(),
+                    directory,
+                    cached.and_then(|cached| cached.find(directory)),
+                )?;
+
+                tree_entries.push(TreeEntry::new(0o040000, directory.to_string(), subtree_id)?);
+                children.push(subtree);
+
+                position += length;
+            }
+            None => {
+                let entry = entries[position];
+                tree_entries.push(TreeEntry::new(
+                    entry.mode(),
+                    name.to_string(),
+                    entry.oid().clone(),
+                )?);
+
+                position += 1;
+            }
+        }
+    }
+
+    let tree_id = write_object(
+        git_dir,
+        packs,
+        &Object::Tree(Tree::new(tree_entries)?).to_raw(),
+    )?;
+    let cache_tree = CacheTree::new(name.to_string(), entries.len(), tree_id.clone(), children);
 
-    Ok(blob_id)
+    Ok((tree_id, cache_tree))
 }
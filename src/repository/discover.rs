@@ -0,0 +1,216 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use super::{GitError, GitResult, GIT_DIR, GIT_DIR_ENV};
+
+const WORK_TREE_ENV: &str = "GRIT_WORK_TREE";
+/// colon separated directories above which discovery does not look
+const CEILING_DIRECTORIES_ENV: &str = "GRIT_CEILING_DIRECTORIES";
+/// prefix of the content of a `.grit` file pointing to the actual git dir
+const GITFILE_PREFIX: &str = "gitdir: ";
+
+/// The repository a command runs in, and where it runs from
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RepositoryLocation {
+    git_dir: PathBuf,
+    /// root of the working tree, unless the repository is bare
+    work_tree: Option<PathBuf>,
+    /// current directory relative to the root of the working tree, with a
+    /// trailing slash unless it is the root itself
+    prefix: String,
+}
+
+impl RepositoryLocation {
+    /// Finds the repository from `GRIT_DIR` and `GRIT_WORK_TREE` when set,
+    /// otherwise by looking for `.grit` in the current directory and then in
+    /// each parent, up to the ones listed in `GRIT_CEILING_DIRECTORIES`
+    pub fn discover() -> GitResult<Self> {
+        let current_dir = env::current_dir()?;
+        let work_tree = env::var_os(WORK_TREE_ENV).map(|work_tree| current_dir.join(work_tree));
+
+        if let Some(git_dir) = env::var_os(GIT_DIR_ENV) {
+            // the current directory is the root of the working tree by default
+            let work_tree = work_tree.unwrap_or_else(|| current_dir.clone());
+            return Self::new(PathBuf::from(git_dir), Some(work_tree), &current_dir);
+        }
+
+        let ceilings: Vec<PathBuf> = env::var(CEILING_DIRECTORIES_ENV)
+            .unwrap_or_default()
+            .split(':')
+            .filter(|ceiling| !ceiling.is_empty())
+            .map(|ceiling| Path::new(ceiling).canonicalize().unwrap_or(ceiling.into()))
+            .collect();
+
+        let mut location = Self::discover_from(&current_dir, &ceilings)?;
+        if let Some(work_tree) = work_tree {
+            location = Self::new(location.git_dir, Some(work_tree), &current_dir)?;
+        }
+
+        Ok(location)
+    }
+
+    fn discover_from(current_dir: &Path, ceilings: &[PathBuf]) -> GitResult<Self> {
+        for directory in current_dir.ancestors() {
+            if directory != current_dir && ceilings.iter().any(|ceiling| ceiling == directory) {
+                break;
+            }
+
+            if let Some(location) = Self::find(directory, current_dir)? {
+                return Ok(location);
+            }
+        }
+
+        Err(GitError::NotARepository(GIT_DIR.to_string()))
+    }
+
+    /// Opens the repository at `path`, either a working tree containing
+    /// `.grit` or a git dir, which is then bare
+    pub fn open(path: &Path) -> GitResult<Self> {
+        let path = path.canonicalize()?;
+        Self::find(&path, &env::current_dir()?)?
+            .ok_or_else(|| GitError::NotARepository(path.display().to_string()))
+    }
+
+    fn find(directory: &Path, current_dir: &Path) -> GitResult<Option<Self>> {
+        let dot_git = directory.join(GIT_DIR);
+        let location = if dot_git.is_file() {
+            let git_dir = read_gitfile(&dot_git)?;
+            Self::new(git_dir, Some(directory.to_path_buf()), current_dir)?
+        } else if is_git_dir(&dot_git) {
+            Self::new(dot_git, Some(directory.to_path_buf()), current_dir)?
+        } else if is_git_dir(directory) {
+            Self::new(directory.to_path_buf(), None, current_dir)?
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(location))
+    }
+
+    pub(super) fn new(
+        git_dir: PathBuf,
+        work_tree: Option<PathBuf>,
+        current_dir: &Path,
+    ) -> GitResult<Self> {
+        let work_tree = work_tree
+            .map(|work_tree| work_tree.canonicalize())
+            .transpose()?;
+
+        // outside of the working tree, paths are relative to its root
+        let prefix = work_tree
+            .as_ref()
+            .and_then(|work_tree| current_dir.strip_prefix(work_tree).ok())
+            .and_then(Path::to_str)
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| format!("{prefix}/"))
+            .unwrap_or_default();
+
+        Ok(Self {
+            git_dir,
+            work_tree,
+            prefix,
+        })
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+}
+
+/// Whether the directory looks like a git dir, with the files `init` creates
+pub fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+/// Writes a `.grit` file in the working tree pointing to a git dir stored
+/// elsewhere
+pub fn write_gitfile(work_tree: &Path, git_dir: &Path) -> GitResult<()> {
+    let git_dir = env::current_dir()?.join(git_dir);
+    fs::write(
+        work_tree.join(GIT_DIR),
+        format!("{GITFILE_PREFIX}{}\n", git_dir.display()),
+    )?;
+
+    Ok(())
+}
+
+/// Reads a `.grit` file containing `gitdir: <path>`, where the path is
+/// relative to the directory of the file
+fn read_gitfile(path: &Path) -> GitResult<PathBuf> {
+    let content = fs::read_to_string(path)?;
+    let git_dir = content
+        .trim_end()
+        .strip_prefix(GITFILE_PREFIX)
+        .ok_or_else(|| GitError::InvalidGitFile(path.display().to_string()))?;
+
+    let git_dir = path
+        .parent()
+        .expect("a file always has a parent")
+        .join(git_dir);
+    if !is_git_dir(&git_dir) {
+        return Err(GitError::InvalidGitFile(path.display().to_string()));
+    }
+
+    Ok(git_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    fn create_git_dir(path: &Path) {
+        fs::create_dir_all(path.join("objects")).unwrap();
+        fs::create_dir_all(path.join("refs")).unwrap();
+        fs::write(path.join("HEAD"), "ref: refs/heads/master").unwrap();
+    }
+
+    #[test]
+    pub fn finds_repository_in_parent_directories() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp_dir.path().join("repository/a/b")).unwrap();
+        // the working tree is canonical, and the temporary directory may not be
+        let root = temp_dir.path().canonicalize().unwrap();
+        let nested = root.join("repository/a/b");
+        create_git_dir(&root.join("repository").join(GIT_DIR));
+
+        let location = RepositoryLocation::discover_from(&nested, &[]).unwrap();
+        assert_eq!(location.git_dir(), root.join("repository").join(GIT_DIR));
+        assert_eq!(
+            location.work_tree(),
+            Some(root.join("repository").as_path())
+        );
+        assert_eq!(location.prefix(), "a/b/");
+
+        let ceilings = [root.join("repository/a")];
+        assert!(matches!(
+            RepositoryLocation::discover_from(&nested, &ceilings),
+            Err(GitError::NotARepository(_))
+        ));
+
+        // a gitfile in a subdirectory points to another git dir
+        create_git_dir(&root.join("separate"));
+        fs::write(
+            root.join("repository/a").join(GIT_DIR),
+            "gitdir: ../../separate\n",
+        )
+        .unwrap();
+        let location = RepositoryLocation::discover_from(&nested, &[]).unwrap();
+        assert_eq!(location.git_dir(), root.join("repository/a/../../separate"));
+        assert_eq!(location.prefix(), "b/");
+
+        let location = RepositoryLocation::discover_from(&root.join("separate"), &[]).unwrap();
+        assert_eq!(location.work_tree(), None);
+    }
+}
@@ -0,0 +1,506 @@
+use std::{
+    env,
+    fs::{self, create_dir_all},
+    io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    config::{Config, ConfigFile},
+    fsck::{self, FsckIssue},
+    index::Index,
+    object::{Commit, Object, ObjectKind, Oid, RawObject, Signature, Tag, Tree},
+    pack::DeltaOptions,
+};
+
+use super::{
+    default_index_version, delete_reference, expire_reflogs, get_object_path,
+    identity::{self, Role},
+    is_valid_reference_name, list_references, list_working_tree_files, needs_gc, normalize_path,
+    object_exists, pack_references, prune, prune_packed, reachable_objects, read_index,
+    read_object, read_object_header, read_raw_object, read_reference, read_symbolic_reference,
+    read_tree_ish, reference_exists, repack, resolve_head, resolve_reference_name,
+    resolve_revision, update_reference, write_index, write_object, write_pack_files,
+    write_symbolic_reference, write_tree, GitError, GitResult, PackCache, RepackOptions,
+    RepositoryLocation, DEFAULT_BRANCH,
+};
+
+/// directory whose files are copied into new git dirs, such as hooks
+const TEMPLATE_DIR_ENV: &str = "GRIT_TEMPLATE_DIR";
+
+#[derive(Clone, Default, Debug)]
+pub struct InitOptions {
+    /// Branch `HEAD` points to, `init.defaultBranch` or `master` by default
+    pub initial_branch: Option<String>,
+    /// Directory copied into the git dir, `GRIT_TEMPLATE_DIR` or
+    /// `init.templateDir` by default
+    pub template_dir: Option<PathBuf>,
+}
+
+/// A repository and the object database, index and references stored in its
+/// git dir. Its methods return values rather than printing them, so that it
+/// can be used as a library
+#[derive(Clone, Debug)]
+pub struct Repository {
+    location: RepositoryLocation,
+    config: Config,
+    /// shared by clones, as they read the same object database
+    packs: Arc<PackCache>,
+}
+
+impl Repository {
+    /// Finds the repository the current directory belongs to, as git does
+    pub fn discover() -> GitResult<Self> {
+        Self::from_location(RepositoryLocation::discover()?)
+    }
+
+    /// Opens the repository at `path`, either a working tree containing
+    /// `.grit` or a bare git dir
+    pub fn open<P: AsRef<Path>>(path: P) -> GitResult<Self> {
+        Self::from_location(RepositoryLocation::open(path.as_ref())?)
+    }
+
+    fn from_location(location: RepositoryLocation) -> GitResult<Self> {
+        let config = Config::load(Some(location.git_dir()))?;
+        Ok(Self {
+            location,
+            config,
+            packs: Arc::default(),
+        })
+    }
+
+    /// Creates the git dir, or completes an existing one, with `HEAD` pointing
+    /// to the initial branch. The repository is bare without a working tree
+    pub fn init(
+        git_dir: PathBuf,
+        work_tree: Option<PathBuf>,
+        options: &InitOptions,
+    ) -> GitResult<Self> {
+        if let Some(branch) = &options.initial_branch {
+            verify_branch_name(branch)?;
+        }
+        create_dir_all(&git_dir)?;
+        // settings such as `init.defaultBranch` usually come from the user
+        let config = Config::load(Some(&git_dir))?;
+
+        let config_path = git_dir.join("config");
+        let new_config = !config_path.exists();
+
+        let template_dir = match &options.template_dir {
+            Some(template_dir) => Some(template_dir.clone()),
+            None => env::var_os(TEMPLATE_DIR_ENV)
+                .map(PathBuf::from)
+                .or_else(|| config.get_string("init.templateDir").map(PathBuf::from)),
+        };
+        // an empty template directory disables templates
+        if let Some(template_dir) = template_dir.filter(|dir| !dir.as_os_str().is_empty()) {
+            copy_template(&template_dir, &git_dir)?;
+        }
+
+        let head = git_dir.join("HEAD");
+        if !head.exists() {
+            let branch = match &options.initial_branch {
+                Some(branch) => branch.as_str(),
+                None => config
+                    .get_string("init.defaultBranch")
+                    .unwrap_or(DEFAULT_BRANCH),
+            };
+            verify_branch_name(branch)?;
+            write_symbolic_reference(&git_dir, "HEAD", &format!("refs/heads/{branch}"))?;
+        }
+
+        if new_config {
+            // a template may provide a configuration to start from
+            let bare = work_tree.is_none();
+            let mut config = ConfigFile::read(&config_path)?;
+            config.set("core.repositoryformatversion", "0", None)?;
+            config.set("core.filemode", "true", None)?;
+            config.set("core.bare", &bare.to_string(), None)?;
+            if !bare {
+                config.set("core.logallrefupdates", "true", None)?;
+            }
+            config.write(&config_path)?;
+        }
+
+        for directory in [
+            "branches",
+            "hooks",
+            "info",
+            "objects/info",
+            "objects/pack",
+            "refs/heads",
+            "refs/tags",
+        ] {
+            create_dir_all(git_dir.join(directory))?;
+        }
+
+        Self::from_location(RepositoryLocation::new(
+            git_dir,
+            work_tree,
+            &env::current_dir()?,
+        )?)
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        self.location.git_dir()
+    }
+
+    /// Root of the working tree, unless the repository is bare
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.location.work_tree()
+    }
+
+    /// Root of the working tree, failing for bare repositories
+    pub fn require_work_tree(&self) -> GitResult<&Path> {
+        self.work_tree().ok_or(GitError::NoWorkTree)
+    }
+
+    /// Current directory relative to the root of the working tree
+    pub fn prefix(&self) -> &str {
+        self.location.prefix()
+    }
+
+    /// Configuration of every scope, as read when the repository was opened
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Signature of the author of new commits, dated now unless
+    /// `GIT_AUTHOR_DATE` is set
+    pub fn author(&self) -> GitResult<Signature> {
+        identity::signature(&self.config, Role::Author, |name| env::var(name).ok())
+    }
+
+    /// Signature of the committer of new commits and of new tags, dated now
+    /// unless `GIT_COMMITTER_DATE` is set
+    pub fn committer(&self) -> GitResult<Signature> {
+        identity::signature(&self.config, Role::Committer, |name| env::var(name).ok())
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.git_dir().join("config")
+    }
+
+    pub fn object_exists(&self, oid: &Oid) -> GitResult<bool> {
+        object_exists(self.git_dir(), &self.packs, oid)
+    }
+
+    pub fn read_raw_object(&self, oid: &Oid) -> GitResult<RawObject> {
+        read_raw_object(self.git_dir(), &self.packs, oid)
+    }
+
+    pub fn read_object(&self, oid: &Oid) -> GitResult<Object> {
+        read_object(self.git_dir(), &self.packs, oid)
+    }
+
+    pub fn read_object_header(&self, oid: &Oid) -> GitResult<(ObjectKind, usize)> {
+        read_object_header(self.git_dir(), &self.packs, oid)
+    }
+
+    /// Reads a tree, or the tree of a commit
+    pub fn read_tree(&self, oid: &Oid) -> GitResult<Tree> {
+        read_tree_ish(self.git_dir(), &self.packs, oid)
+    }
+
+    pub fn write_object(&self, object: &RawObject) -> GitResult<Oid> {
+        write_object(self.git_dir(), &self.packs, object)
+    }
+
+    pub fn write_blob(&self, content: Vec<u8>) -> GitResult<Oid> {
+        self.write_object(&RawObject::new(ObjectKind::Blob, content))
+    }
+
+    /// Writes a commit object, without updating any reference
+    pub fn commit_tree(
+        &self,
+        tree_id: Oid,
+        parents: Vec<Oid>,
+        message: String,
+        author: Signature,
+        committer: Signature,
+    ) -> GitResult<Oid> {
+        let (kind, _) = self.read_object_header(&tree_id)?;
+        if kind != ObjectKind::Tree {
+            return Err(GitError::UnexpectedObjectKind {
+                expected: ObjectKind::Tree,
+                found: kind,
+            });
+        }
+
+        let commit = Commit::new(parents, tree_id, message, author, committer)?;
+        self.write_object(&Object::Commit(commit).to_raw())
+    }
+
+    /// Writes a tag object for an existing object, checking its type
+    pub fn write_tag(&self, tag: Tag) -> GitResult<Oid> {
+        let (kind, _) = self.read_object_header(tag.object_id())?;
+        if kind != tag.object_kind() {
+            return Err(GitError::UnexpectedObjectKind {
+                expected: tag.object_kind(),
+                found: kind,
+            });
+        }
+
+        self.write_object(&Object::Tag(tag).to_raw())
+    }
+
+    pub fn resolve_revision(&self, revision: &str) -> GitResult<Oid> {
+        resolve_revision(self.git_dir(), revision)
+    }
+
+    pub fn head(&self) -> GitResult<Oid> {
+        resolve_head(self.git_dir())
+    }
+
+    /// Reads a reference given by its full name, such as `refs/heads/master`
+    pub fn read_reference(&self, name: &str) -> GitResult<Oid> {
+        read_reference(self.git_dir(), name)
+    }
+
+    pub fn reference_exists(&self, name: &str) -> GitResult<bool> {
+        reference_exists(self.git_dir(), name)
+    }
+
+    /// Points the reference `name` resolves to at an object, so updating
+    /// `HEAD` updates the current branch, or `HEAD` itself when detached
+    pub fn update_reference(&self, name: &str, oid: &Oid) -> GitResult<()> {
+        update_reference(self.git_dir(), name, oid)
+    }
+
+    /// Reads the reference a symbolic reference such as `HEAD` points to, or
+    /// `None` if it is detached
+    pub fn symbolic_reference(&self, name: &str) -> GitResult<Option<String>> {
+        read_symbolic_reference(self.git_dir(), name)
+    }
+
+    pub fn set_symbolic_reference(&self, name: &str, target: &str) -> GitResult<()> {
+        write_symbolic_reference(self.git_dir(), name, target)
+    }
+
+    /// Follows symbolic references to the name of the one storing an object id
+    pub fn resolve_reference_name(&self, name: &str) -> GitResult<String> {
+        resolve_reference_name(self.git_dir(), name)
+    }
+
+    pub fn delete_reference(&self, name: &str) -> GitResult<()> {
+        delete_reference(self.git_dir(), name)
+    }
+
+    /// Lists the references under a directory such as `refs/tags`
+    pub fn list_references(&self, prefix: &str) -> GitResult<Vec<(String, Oid)>> {
+        list_references(self.git_dir(), prefix)
+    }
+
+    pub fn index(&self) -> GitResult<Index> {
+        read_index(self.git_dir(), default_index_version(&self.config)?)
+    }
+
+    pub fn write_index(&self, index: &mut Index) -> GitResult<()> {
+        write_index(self.git_dir(), self.work_tree(), index)
+    }
+
+    /// Writes the trees of the index, returning the one at `prefix`, and
+    /// updating the cached trees of the index
+    pub fn write_tree(&self, index: &mut Index, prefix: &str) -> GitResult<Oid> {
+        write_tree(self.git_dir(), &self.packs, index, prefix)
+    }
+
+    /// Converts a path relative to the current directory to the name of its
+    /// index entry
+    pub fn normalize_path(&self, path: &Path) -> GitResult<String> {
+        normalize_path(self.git_dir(), self.require_work_tree()?, path)
+    }
+
+    /// Lists the files of the working tree, relative to its root
+    pub fn working_tree_files(&self) -> GitResult<Vec<String>> {
+        list_working_tree_files(self.git_dir(), self.require_work_tree()?)
+    }
+
+    /// Lists the objects reachable from `include` but not from `exclude`
+    pub fn reachable_objects(&self, include: &[Oid], exclude: &[Oid]) -> GitResult<Vec<Oid>> {
+        reachable_objects(self.git_dir(), &self.packs, include, exclude)
+    }
+
+    /// Writes the objects to `<base_name>-<checksum>.pack` and its index,
+    /// returning the checksum
+    pub fn write_pack_files(
+        &self,
+        base_name: &Path,
+        oids: &[Oid],
+        options: DeltaOptions,
+    ) -> GitResult<Oid> {
+        write_pack_files(self.git_dir(), &self.packs, base_name, oids, options)
+    }
+
+    /// Packs reachable objects, returning the checksum of the new pack, if any
+    pub fn repack(&self, options: RepackOptions) -> GitResult<Option<Oid>> {
+        repack(self.git_dir(), &self.packs, options)
+    }
+
+    /// Removes loose objects which are packed, returning them
+    pub fn prune_packed(&self, dry_run: bool) -> GitResult<Vec<Oid>> {
+        prune_packed(self.git_dir(), dry_run)
+    }
+
+    /// Removes unreachable loose objects not modified after `expire`,
+    /// returning them
+    pub fn prune(&self, expire: i64, dry_run: bool) -> GitResult<Vec<Oid>> {
+        prune(self.git_dir(), &self.packs, expire, dry_run)
+    }
+
+    pub fn pack_references(&self, all: bool, prune: bool) -> GitResult<()> {
+        pack_references(self.git_dir(), all, prune)
+    }
+
+    pub fn expire_reflogs(&self, expire: i64) -> GitResult<()> {
+        expire_reflogs(self.git_dir(), expire)
+    }
+
+    /// Whether there are more loose objects than `threshold`, or too many packs
+    pub fn needs_gc(&self, threshold: usize) -> GitResult<bool> {
+        needs_gc(self.git_dir(), threshold)
+    }
+
+    pub fn object_path(&self, oid: &Oid) -> PathBuf {
+        get_object_path(self.git_dir(), oid)
+    }
+
+    pub fn fsck(&self) -> GitResult<Vec<FsckIssue>> {
+        fsck::fsck(self.git_dir())
+    }
+}
+
+fn verify_branch_name(branch: &str) -> GitResult<()> {
+    if !is_valid_reference_name(&format!("refs/heads/{branch}")) {
+        return Err(GitError::InvalidReferenceName(branch.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Copies the files of the template into the git dir, recursively, keeping
+/// the ones which already exist
+fn copy_template(template_dir: &Path, git_dir: &Path) -> GitResult<()> {
+    let entries = match fs::read_dir(template_dir) {
+        Ok(entries) => entries,
+        // as with git, a missing template only means there is nothing to copy
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error.into()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let target = git_dir.join(entry.file_name());
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            create_dir_all(&target)?;
+            copy_template(&entry.path(), &target)?;
+        } else if target.symlink_metadata().is_ok() {
+            continue;
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            // the permissions are copied too, keeping hooks executable
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn commits_are_written_through_the_handle() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().to_path_buf();
+        let repository = Repository::init(
+            root.join(".grit"),
+            Some(root.clone()),
+            &InitOptions::default(),
+        )
+        .unwrap();
+
+        let blob_id = repository.write_blob(b"content".to_vec()).unwrap();
+        let Object::Blob(blob) = repository.read_object(&blob_id).unwrap() else {
+            panic!("expected a blob");
+        };
+        assert_eq!(blob.content(), b"content");
+
+        // a commit must point to a tree
+        let signature = Signature::new("A U Thor".to_string(), "a@b.c".to_string(), 0, 0).unwrap();
+        assert!(matches!(
+            repository.commit_tree(
+                blob_id,
+                vec![],
+                "message\n".to_string(),
+                signature.clone(),
+                signature.clone()
+            ),
+            Err(GitError::UnexpectedObjectKind { .. })
+        ));
+
+        let tree_id = repository.write_tree(&mut Index::default(), "").unwrap();
+        let commit_id = repository
+            .commit_tree(
+                tree_id,
+                vec![],
+                "message\n".to_string(),
+                signature.clone(),
+                signature,
+            )
+            .unwrap();
+        repository
+            .update_reference("refs/heads/master", &commit_id)
+            .unwrap();
+        assert_eq!(repository.head().unwrap(), commit_id);
+
+        let reopened = Repository::open(&root).unwrap();
+        assert_eq!(reopened.read_tree(&commit_id).unwrap().entries().len(), 0);
+    }
+
+    #[test]
+    pub fn init_copies_the_template() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().to_path_buf();
+        fs::create_dir_all(root.join("template/hooks")).unwrap();
+        fs::write(root.join("template/hooks/hook"), "#!/bin/sh\n").unwrap();
+        fs::write(root.join("template/config"), "[user]\n\tname = Template\n").unwrap();
+
+        let options = InitOptions {
+            initial_branch: Some("main".to_string()),
+            template_dir: Some(root.join("template")),
+        };
+        let repository = Repository::init(root.join("bare.git"), None, &options).unwrap();
+
+        let git_dir = root.join("bare.git");
+        assert_eq!(
+            fs::read_to_string(git_dir.join("HEAD")).unwrap(),
+            "ref: refs/heads/main\n"
+        );
+        assert!(git_dir.join("hooks/hook").is_file());
+        assert!(git_dir.join("objects/pack").is_dir());
+        assert_eq!(
+            repository.config().get_string("user.name"),
+            Some("Template")
+        );
+        assert_eq!(
+            repository.config().get_bool("core.bare").unwrap(),
+            Some(true)
+        );
+
+        let options = InitOptions {
+            initial_branch: Some("bad..name".to_string()),
+            ..InitOptions::default()
+        };
+        assert!(Repository::init(root.join("other.git"), None, &options).is_err());
+        assert!(!root.join("other.git").exists());
+    }
+}
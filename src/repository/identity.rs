@@ -0,0 +1,201 @@
+use std::{ffi::CStr, mem, ptr};
+
+use crate::{config::Config, object::Signature};
+
+use super::{GitError, GitResult};
+
+/// size above which the account database is not worth reading
+const MAX_ACCOUNT_BUFFER: usize = 1 << 20;
+
+/// Whose identity a signature records
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    /// Variables overriding the name, email and date
+    fn environment(self) -> [&'static str; 3] {
+        match self {
+            Role::Author => ["GIT_AUTHOR_NAME", "GIT_AUTHOR_EMAIL", "GIT_AUTHOR_DATE"],
+            Role::Committer => [
+                "GIT_COMMITTER_NAME",
+                "GIT_COMMITTER_EMAIL",
+                "GIT_COMMITTER_DATE",
+            ],
+        }
+    }
+
+    /// Section overriding `user` for this role only
+    fn section(self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Role::Author => "Author",
+            Role::Committer => "Committer",
+        }
+    }
+}
+
+/// Builds the signature of the role from the environment, looked up through
+/// `environment`, then the configuration and finally the account of the
+/// current user, dated now unless the date is overridden
+pub(super) fn signature(
+    config: &Config,
+    role: Role,
+    environment: impl Fn(&str) -> Option<String>,
+) -> GitResult<Signature> {
+    let [name_env, email_env, date_env] = role.environment();
+    let section = role.section();
+
+    let name = environment(name_env)
+        .or_else(|| {
+            config
+                .get_string(&format!("{section}.name"))
+                .map(str::to_string)
+        })
+        .or_else(|| config.get_string("user.name").map(str::to_string))
+        .or_else(system_name)
+        .map(|name| strip_crud(&name).to_string())
+        .filter(|name| !name.is_empty());
+    let email = environment(email_env)
+        .or_else(|| {
+            config
+                .get_string(&format!("{section}.email"))
+                .map(str::to_string)
+        })
+        .or_else(|| config.get_string("user.email").map(str::to_string))
+        .or_else(|| environment("EMAIL"))
+        .or_else(system_email)
+        .map(|email| strip_crud(&email).to_string());
+
+    let (Some(name), Some(email)) = (name, email) else {
+        return Err(GitError::UnknownIdentity(role.title()));
+    };
+
+    Signature::with_date(name, email, environment(date_env).as_deref())
+}
+
+/// Trims the whitespace and punctuation git strips around names and emails
+fn strip_crud(value: &str) -> &str {
+    value.trim_matches(|c: char| {
+        c <= ' ' || matches!(c, '.' | ',' | ':' | ';' | '<' | '>' | '"' | '\\' | '\'')
+    })
+}
+
+/// Full name from the account database, where `&` stands for the
+/// capitalized login name
+fn system_name() -> Option<String> {
+    let (login, gecos) = system_account()?;
+    let full_name = gecos.split(',').next().unwrap_or_default();
+
+    let mut capitalized = login.clone();
+    if let Some(first) = capitalized.get_mut(..1) {
+        first.make_ascii_uppercase();
+    }
+
+    Some(full_name.replace('&', &capitalized))
+}
+
+/// `<login>@<hostname>`, as long as the hostname includes a domain, since
+/// an address without one cannot be anybody's
+fn system_email() -> Option<String> {
+    let (login, _) = system_account()?;
+
+    let mut hostname = [0; 256];
+    // SAFETY: the buffer outlives the call, which leaves its last byte alone
+    let result = unsafe { libc::gethostname(hostname.as_mut_ptr(), hostname.len() - 1) };
+    if result != 0 {
+        return None;
+    }
+    // SAFETY: the last byte is still zero, so the name is terminated
+    let hostname = unsafe { CStr::from_ptr(hostname.as_ptr()) }.to_str().ok()?;
+
+    hostname
+        .contains('.')
+        .then(|| format!("{login}@{hostname}"))
+}
+
+/// Login and GECOS field of the current user
+fn system_account() -> Option<(String, String)> {
+    // SAFETY: an all-zero passwd is valid, its pointers are only read once
+    // getpwuid_r has filled it
+    let mut entry: libc::passwd = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+
+    loop {
+        // SAFETY: every pointer outlives the call, and the buffer length is
+        // the one given
+        let error = unsafe {
+            libc::getpwuid_r(
+                libc::getuid(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match error {
+            0 if !result.is_null() => break,
+            libc::ERANGE if buffer.len() < MAX_ACCOUNT_BUFFER => buffer.resize(buffer.len() * 2, 0),
+            _ => return None,
+        }
+    }
+
+    // SAFETY: the strings point into the buffer, which is still alive
+    unsafe {
+        let login = CStr::from_ptr(entry.pw_name).to_str().ok()?.to_string();
+        let gecos = if entry.pw_gecos.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(entry.pw_gecos).to_str().ok()?.to_string()
+        };
+
+        Some((login, gecos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+    use crate::config::{ConfigFile, ConfigScope};
+
+    #[test]
+    pub fn configuration_of_the_role_overrides_the_user() {
+        let file = ConfigFile::parse(
+            "[user]\n\tname = User\n\temail = user@example.com\n[committer]\n\tname = Committer\n",
+            "config",
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.push_file(&file, ConfigScope::Global);
+
+        let committer = signature(&config, Role::Committer, |_| None).unwrap();
+        assert_eq!(committer.name(), "Committer");
+        assert_eq!(committer.email(), "user@example.com");
+
+        let author = signature(&config, Role::Author, |name| match name {
+            "GIT_AUTHOR_NAME" => Some(" \"Author.\" ".to_string()),
+            "GIT_AUTHOR_EMAIL" => Some("<author@example.com>".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(author.name(), "Author");
+        assert_eq!(author.email(), "author@example.com");
+
+        // a newline would forge the headers following the signature
+        assert!(signature(&config, Role::Author, |name| {
+            (name == "GIT_AUTHOR_NAME").then(|| "Evil> 0 +0000\nparent 0\nx".to_string())
+        })
+        .is_err());
+    }
+}
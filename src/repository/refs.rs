@@ -0,0 +1,453 @@
+use std::{
+    fs::{self, create_dir_all},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    object::{Object, Oid},
+    utils::write_locked,
+};
+
+use super::{read_object, remove_empty_parents, GitError, GitResult, PackCache};
+
+const PACKED_REFERENCES: &str = "packed-refs";
+const PACKED_REFERENCES_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+/// prefix of the content of a reference pointing to another one
+const SYMBOLIC_REFERENCE_PREFIX: &str = "ref: ";
+
+/// What a reference stores, before following it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ReferenceTarget {
+    /// an object id, as in a branch or a detached `HEAD`
+    Object(Oid),
+    /// the name of another reference, as in `HEAD` on a branch
+    Symbolic(String),
+}
+
+/// Checks a reference name against the rules of `git check-ref-format`
+pub fn is_valid_reference_name(name: &str) -> bool {
+    const FORBIDDEN: &[char] = &[' ', '~', '^', ':', '?', '*', '[', '\\'];
+
+    !name.is_empty()
+        && name != "@"
+        && !name.starts_with('/')
+        && !name.starts_with('-')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || FORBIDDEN.contains(&c))
+        && name
+            .split('/')
+            .all(|component| !component.starts_with('.') && !component.ends_with(".lock"))
+}
+
+/// Whether a reference may be written or deleted: `HEAD`, a name under
+/// `refs/` or a pseudo-reference such as `ORIG_HEAD`, but never other files of
+/// the git dir such as `config` or `index`
+fn is_writable_reference_name(name: &str) -> bool {
+    let pseudo_reference = name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+
+    is_valid_reference_name(name)
+        && (name == "HEAD" || name.starts_with("refs/") || pseudo_reference)
+}
+
+/// Writes a loose reference file through a lock file
+fn write_reference_file(git_dir: &Path, name: &str, content: String) -> GitResult<()> {
+    if !is_writable_reference_name(name) {
+        return Err(GitError::InvalidReferenceName(name.to_string()));
+    }
+
+    let path = git_dir.join(name);
+    if let Some(base) = path.parent() {
+        create_dir_all(base)?;
+    }
+    write_locked(&path, content)?;
+
+    Ok(())
+}
+
+/// Reads what a reference stores without following it, looking first for a
+/// loose reference file and then in `packed-refs`
+pub fn read_reference_target(git_dir: &Path, name: &str) -> GitResult<ReferenceTarget> {
+    match fs::read_to_string(git_dir.join(name)) {
+        Ok(content) => match content.trim_end().strip_prefix(SYMBOLIC_REFERENCE_PREFIX) {
+            Some(target) => {
+                let target = target.trim_start();
+                // the target is joined to the git dir, so it must stay inside
+                if !is_valid_reference_name(target) {
+                    return Err(GitError::InvalidReferenceName(target.to_string()));
+                }
+                Ok(ReferenceTarget::Symbolic(target.to_string()))
+            }
+            None => Ok(ReferenceTarget::Object(Oid::new(content.trim_end())?)),
+        },
+        Err(error) if error.kind() == io::ErrorKind::NotFound => read_packed_references(git_dir)?
+            .into_iter()
+            .find(|(packed_name, _)| packed_name == name)
+            .map(|(_, oid)| ReferenceTarget::Object(oid))
+            .ok_or_else(|| GitError::ReferenceNotFound(name.to_string())),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Follows symbolic references from `name` to the one storing an object id,
+/// returning its name and the id unless it does not exist yet, as with the
+/// branch of a new repository
+fn follow_reference(git_dir: &Path, name: &str) -> GitResult<(String, Option<Oid>)> {
+    let mut followed = vec![name.to_string()];
+    loop {
+        let current = followed.last().expect("the first name is always followed");
+        match read_reference_target(git_dir, current) {
+            Ok(ReferenceTarget::Object(oid)) => return Ok((current.clone(), Some(oid))),
+            Ok(ReferenceTarget::Symbolic(target)) => {
+                if followed.contains(&target) {
+                    return Err(GitError::SymbolicReferenceLoop(name.to_string()));
+                }
+                followed.push(target);
+            }
+            Err(GitError::ReferenceNotFound(_)) => return Ok((current.clone(), None)),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Name of the reference storing the object id `name` resolves to, which is
+/// `name` itself unless it is symbolic
+pub fn resolve_reference_name(git_dir: &Path, name: &str) -> GitResult<String> {
+    follow_reference(git_dir, name).map(|(name, _)| name)
+}
+
+/// Reads the object id a reference resolves to, following symbolic references
+pub fn read_reference(git_dir: &Path, name: &str) -> GitResult<Oid> {
+    match follow_reference(git_dir, name)? {
+        (_, Some(oid)) => Ok(oid),
+        (name, None) => Err(GitError::ReferenceNotFound(name)),
+    }
+}
+
+/// Reads the reference a symbolic reference points to, without following it
+/// further, or `None` if it stores an object id
+pub fn read_symbolic_reference(git_dir: &Path, name: &str) -> GitResult<Option<String>> {
+    match read_reference_target(git_dir, name)? {
+        ReferenceTarget::Symbolic(target) => Ok(Some(target)),
+        ReferenceTarget::Object(_) => Ok(None),
+    }
+}
+
+/// Points a reference to another one under `refs/`, which does not need to
+/// exist yet
+pub fn write_symbolic_reference(git_dir: &Path, name: &str, target: &str) -> GitResult<()> {
+    if !target.starts_with("refs/") || !is_valid_reference_name(target) {
+        return Err(GitError::InvalidSymbolicReference(target.to_string()));
+    }
+
+    write_reference_file(
+        git_dir,
+        name,
+        format!("{SYMBOLIC_REFERENCE_PREFIX}{target}\n"),
+    )
+}
+
+/// Writes a loose reference, which overrides any packed one, replacing it even
+/// if it is symbolic
+pub fn write_reference(git_dir: &Path, name: &str, oid: &Oid) -> GitResult<()> {
+    write_reference_file(git_dir, name, format!("{oid}\n"))
+}
+
+/// Writes the reference `name` resolves to, so updating a symbolic `HEAD`
+/// updates its branch, and a detached one `HEAD` itself
+pub fn update_reference(git_dir: &Path, name: &str, oid: &Oid) -> GitResult<()> {
+    write_reference(git_dir, &resolve_reference_name(git_dir, name)?, oid)
+}
+
+pub fn reference_exists(git_dir: &Path, name: &str) -> GitResult<bool> {
+    Ok(git_dir.join(name).is_file()
+        || read_packed_references(git_dir)?
+            .iter()
+            .any(|(packed_name, _)| packed_name == name))
+}
+
+/// Deletes a reference, both its loose file and its entry in `packed-refs`
+pub fn delete_reference(git_dir: &Path, name: &str) -> GitResult<()> {
+    if !is_writable_reference_name(name) {
+        return Err(GitError::InvalidReferenceName(name.to_string()));
+    }
+
+    let path = git_dir.join(name);
+    let was_loose = path.is_file();
+    if was_loose {
+        fs::remove_file(path)?;
+    }
+
+    let mut packed = read_packed_references(git_dir)?;
+    let packed_count = packed.len();
+    packed.retain(|(packed_name, _)| packed_name != name);
+
+    if packed.len() != packed_count {
+        write_packed_references(git_dir, &packed)?;
+    } else if !was_loose {
+        return Err(GitError::ReferenceNotFound(name.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Reads the references stored in `packed-refs`, skipping the peeled ids of tags
+pub fn read_packed_references(git_dir: &Path) -> GitResult<Vec<(String, Oid)>> {
+    let content = match fs::read_to_string(git_dir.join(PACKED_REFERENCES)) {
+        Ok(content) => content,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut references = Vec::new();
+    for line in content.lines() {
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+
+        let (oid, name) = line
+            .split_once(' ')
+            .ok_or_else(|| GitError::InvalidReferenceName(line.to_string()))?;
+        references.push((name.to_string(), Oid::new(oid)?));
+    }
+
+    Ok(references)
+}
+
+/// Replaces `packed-refs` with the given references, adding the peeled id of
+/// every annotated tag so readers do not need to open it
+fn write_packed_references(git_dir: &Path, references: &[(String, Oid)]) -> GitResult<()> {
+    let mut references = references.to_vec();
+    references.sort();
+
+    // opened once for every reference, rather than once for each
+    let packs = PackCache::default();
+    let mut content = String::from(PACKED_REFERENCES_HEADER);
+    for (name, oid) in &references {
+        content.push_str(&format!("{oid} {name}\n"));
+
+        if let Ok(Object::Tag(_)) = read_object(git_dir, &packs, oid) {
+            let mut peeled = oid.clone();
+            while let Object::Tag(tag) = read_object(git_dir, &packs, &peeled)? {
+                peeled = tag.object_id().clone();
+            }
+            content.push_str(&format!("^{peeled}\n"));
+        }
+    }
+
+    write_locked(&git_dir.join(PACKED_REFERENCES), content)?;
+
+    Ok(())
+}
+
+/// Moves loose references into `packed-refs`, which is faster to read when
+/// there are many of them. Only tags and already packed references are moved
+/// unless `all` is set. Loose files are removed unless `prune` is unset
+pub fn pack_references(git_dir: &Path, all: bool, prune: bool) -> GitResult<()> {
+    let mut packed = read_packed_references(git_dir)?;
+
+    let loose = list_loose_references(git_dir, "refs")?;
+    let mut moved = Vec::new();
+    for (name, oid) in loose {
+        let already_packed = packed.iter().any(|(packed_name, _)| *packed_name == name);
+        if all || already_packed || name.starts_with("refs/tags/") {
+            packed.retain(|(packed_name, _)| *packed_name != name);
+            packed.push((name.clone(), oid?));
+            moved.push(name);
+        }
+    }
+
+    write_packed_references(git_dir, &packed)?;
+
+    if prune {
+        for name in moved {
+            // directories like `refs/heads` are kept even if empty
+            let category: PathBuf = Path::new(&name).components().take(2).collect();
+            fs::remove_file(git_dir.join(&name))?;
+            remove_empty_parents(git_dir.join(&name).parent(), &git_dir.join(category))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves `HEAD`, following the branch it points to unless it is detached
+pub fn resolve_head(git_dir: &Path) -> GitResult<Oid> {
+    read_reference(git_dir, "HEAD")
+}
+
+/// Resolves a revision given as a full object id, `HEAD`, or a reference name,
+/// which is looked up as is if it is a pseudo-reference or a full name, then
+/// under `refs/`, `refs/tags/` and `refs/heads/`
+pub fn resolve_revision(git_dir: &Path, revision: &str) -> GitResult<Oid> {
+    if revision == "HEAD" {
+        return resolve_head(git_dir);
+    }
+
+    if let Ok(oid) = Oid::new(revision) {
+        return Ok(oid);
+    }
+
+    if !is_valid_reference_name(revision) {
+        return Err(GitError::InvalidRevision(revision.to_string()));
+    }
+
+    for prefix in ["", "refs/", "refs/tags/", "refs/heads/"] {
+        // only pseudo-references and full names are looked up in the git dir
+        // itself, where other files such as `config` are not references
+        if prefix.is_empty() && !is_writable_reference_name(revision) {
+            continue;
+        }
+        let name = format!("{prefix}{revision}");
+        if reference_exists(git_dir, &name)? {
+            return read_reference(git_dir, &name);
+        }
+    }
+
+    Err(GitError::InvalidRevision(revision.to_string()))
+}
+
+/// Lists all references under the given directory (e.g. `refs/tags`), sorted
+/// by name, including packed ones unless a loose file overrides them
+pub fn list_references(git_dir: &Path, prefix: &str) -> GitResult<Vec<(String, Oid)>> {
+    let (references, broken) = list_references_and_errors(git_dir, prefix)?;
+    match broken.into_iter().next() {
+        Some((_, error)) => Err(error),
+        None => Ok(references),
+    }
+}
+
+/// References that cannot be read, with the reason
+pub type BrokenReferences = Vec<(String, GitError)>;
+
+/// Lists references like `list_references`, but sets aside the loose ones
+/// that cannot be read along with their error instead of failing
+pub fn list_references_and_errors(
+    git_dir: &Path,
+    prefix: &str,
+) -> GitResult<(Vec<(String, Oid)>, BrokenReferences)> {
+    let mut references = Vec::new();
+    let mut broken = Vec::new();
+    for (name, target) in list_loose_references(git_dir, prefix)? {
+        match target {
+            Ok(oid) => references.push((name, oid)),
+            Err(error) => broken.push((name, error)),
+        }
+    }
+
+    let directory = format!("{prefix}/");
+    for (name, oid) in read_packed_references(git_dir)? {
+        let overridden = references.iter().any(|(loose_name, _)| *loose_name == name)
+            || broken.iter().any(|(broken_name, _)| *broken_name == name);
+        if name.starts_with(&directory) && !overridden {
+            references.push((name, oid));
+        }
+    }
+    references.sort();
+
+    Ok((references, broken))
+}
+
+/// Lists the loose references storing an object id, leaving out symbolic ones
+/// which are listed through the references they point to
+fn list_loose_references(git_dir: &Path, prefix: &str) -> GitResult<Vec<(String, GitResult<Oid>)>> {
+    fn walk(
+        git_dir: &Path,
+        name: &str,
+        references: &mut Vec<(String, GitResult<Oid>)>,
+    ) -> GitResult<()> {
+        let path = git_dir.join(name);
+        if path.is_dir() {
+            for entry in fs::read_dir(path)? {
+                let entry_name = entry?.file_name();
+                match entry_name.to_str() {
+                    Some(entry_name) => walk(git_dir, &format!("{name}/{entry_name}"), references)?,
+                    None => {
+                        let name = format!("{name}/{}", entry_name.to_string_lossy());
+                        let error = GitError::InvalidReferenceName(name.clone());
+                        references.push((name, Err(error)));
+                    }
+                }
+            }
+        } else if path.is_file() {
+            match read_reference_target(git_dir, name) {
+                Ok(ReferenceTarget::Object(oid)) => references.push((name.to_string(), Ok(oid))),
+                Ok(ReferenceTarget::Symbolic(_)) => (),
+                Err(error) => references.push((name.to_string(), Err(error))),
+            }
+        }
+        Ok(())
+    }
+
+    let mut references = Vec::new();
+    walk(git_dir, prefix, &mut references)?;
+    references.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(references)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions_sorted::assert_eq;
+
+    use super::*;
+
+    #[test]
+    pub fn symbolic_references_are_followed_until_an_object_id() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let git_dir = temp_dir.path();
+        let oid = Oid::new("e69de29bb2d1d6434b8b29ae4de4d81e0b7ba5c7").unwrap();
+
+        write_symbolic_reference(git_dir, "HEAD", "refs/heads/main").unwrap();
+        assert!(matches!(
+            read_reference(git_dir, "HEAD"),
+            Err(GitError::ReferenceNotFound(name)) if name == "refs/heads/main"
+        ));
+
+        // updating `HEAD` creates the branch it points to
+        update_reference(git_dir, "HEAD", &oid).unwrap();
+        assert_eq!(read_reference(git_dir, "refs/heads/main").unwrap(), oid);
+        assert_eq!(
+            read_symbolic_reference(git_dir, "HEAD").unwrap(),
+            Some("refs/heads/main".to_string())
+        );
+
+        write_symbolic_reference(git_dir, "refs/heads/main", "refs/heads/other").unwrap();
+        write_symbolic_reference(git_dir, "refs/heads/other", "refs/heads/main").unwrap();
+        assert!(matches!(
+            resolve_head(git_dir),
+            Err(GitError::SymbolicReferenceLoop(name)) if name == "HEAD"
+        ));
+
+        // other files of the git dir are never references
+        for name in ["config", "index", "packed-refs", "objects/info/alternates"] {
+            assert!(matches!(
+                write_symbolic_reference(git_dir, name, "refs/heads/main"),
+                Err(GitError::InvalidReferenceName(_))
+            ));
+            assert!(matches!(
+                write_reference(git_dir, name, &oid),
+                Err(GitError::InvalidReferenceName(_))
+            ));
+        }
+        write_reference(git_dir, "ORIG_HEAD", &oid).unwrap();
+        assert_eq!(resolve_revision(git_dir, "ORIG_HEAD").unwrap(), oid);
+
+        // a tag named after a file of the git dir resolves to the tag
+        fs::write(git_dir.join("config"), "").unwrap();
+        write_reference(git_dir, "refs/tags/config", &oid).unwrap();
+        assert_eq!(resolve_revision(git_dir, "config").unwrap(), oid);
+        assert_eq!(resolve_revision(git_dir, "refs/tags/config").unwrap(), oid);
+
+        // a detached `HEAD` stores the id itself
+        write_reference(git_dir, "HEAD", &oid).unwrap();
+        assert_eq!(read_symbolic_reference(git_dir, "HEAD").unwrap(), None);
+        assert_eq!(resolve_head(git_dir).unwrap(), oid);
+    }
+}
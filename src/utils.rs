@@ -1,4 +1,9 @@
-use std::ops::{BitAnd, Shr};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    ops::{BitAnd, Shr},
+    path::Path,
+};
 
 /// Applies mask to original number and shifts it right
 ///
@@ -18,3 +23,164 @@ where
 {
     (number & mask) >> shift
 }
+
+/// Writes a number in the big endian base 128 encoding used for delta base
+/// offsets in packs and for path prefixes in version 4 indexes, where each
+/// continuation also adds one to avoid redundant encodings
+pub fn write_offset_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+
+    bytes.reverse();
+    buffer.extend_from_slice(&bytes);
+}
+
+/// Reads a number written by `write_offset_varint`
+pub fn read_offset_varint<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut byte = [0; 1];
+    reader.read_exact(&mut byte)?;
+    let mut value = (byte[0] & 0x7f) as u64;
+
+    while byte[0] & 0x80 != 0 {
+        reader.read_exact(&mut byte)?;
+        // the shift must not drop any bit of the value read so far
+        let shifted = value
+            .checked_add(1)
+            .filter(|value| value.leading_zeros() >= 7)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "varint too large"))?;
+        value = (shifted << 7) | (byte[0] & 0x7f) as u64;
+    }
+
+    Ok(value)
+}
+
+/// Replaces a file through `<path>.lock`, which is renamed into place once
+/// complete so readers never see a partial file. As with git, the lock is
+/// created exclusively, so a concurrent writer fails instead of being lost
+pub fn write_locked(path: &Path, content: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+
+    let mut lock = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)?;
+    let result = lock
+        .write_all(content.as_ref())
+        .and_then(|()| fs::rename(&lock_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&lock_path);
+    }
+
+    result
+}
+
+/// Matches text against a shell wildcard pattern, supporting `*`, `?` and
+/// bracket expressions such as `[a-z]` or `[!0-9]`. As with `git tag --list`,
+/// wildcards also match slashes
+pub fn wildmatch(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // position to resume from after the last `*`, as (pattern, text)
+    let mut backtrack = None;
+    let (mut p, mut t) = (0, 0);
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, next)) = match_bracket(&pattern[p..], text[t]) {
+                    if matched {
+                        p += next;
+                        t += 1;
+                        continue;
+                    }
+                } else if text[t] == '[' {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+            }
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        match backtrack {
+            Some((star_p, star_t)) => {
+                p = star_p;
+                t = star_t + 1;
+                backtrack = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches a character against a bracket expression at the start of the
+/// pattern, returning whether it matched and the length of the expression
+fn match_bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &start = pattern.get(i)?;
+        if start == ']' && !first {
+            break;
+        }
+        first = false;
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&end| end != ']') {
+            let end = pattern[i + 2];
+            matched |= start <= c && c <= end;
+            i += 3;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+
+    Some((matched != negated, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn wildmatch_supports_wildcards_and_brackets() {
+        assert!(wildmatch("v*", "v1.0"));
+        assert!(wildmatch("*", "release/v2"));
+        assert!(wildmatch("v?.[0-9]", "v1.2"));
+        assert!(wildmatch("v[!0-1]*", "v2.0"));
+        assert!(!wildmatch("v[!0-1]*", "v1.0"));
+        assert!(!wildmatch("v?", "v10"));
+        assert!(!wildmatch("release", "release/v2"));
+    }
+}